@@ -0,0 +1,18 @@
+use std::{env, fs, path::PathBuf};
+
+// the linker script for the chip picked with a `chip-*`
+// feature, put where cortex-m-rt's link.x can find it
+fn main() {
+    let chip = if env::var_os("CARGO_FEATURE_CHIP_STM32F401").is_some() {
+        "stm32f401"
+    } else {
+        "stm32f411"
+    };
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    fs::copy(format!("memory/{}.x", chip), out.join("memory.x")).unwrap();
+
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory");
+    println!("cargo:rerun-if-changed=build.rs");
+}
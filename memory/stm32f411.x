@@ -1,7 +1,8 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 512K 
+  /* the last sector, at 0x08060000, is kept for settings */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 384K 
   RAM : ORIGIN = 0x20000000, LENGTH = 128K
 }
 
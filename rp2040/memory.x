@@ -0,0 +1,16 @@
+MEMORY
+{
+  /* the second stage bootloader, copied in from rp2040-boot2 */
+  BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
+  FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+  RAM : ORIGIN = 0x20000000, LENGTH = 256K
+}
+
+EXTERN(BOOT2_FIRMWARE)
+
+SECTIONS {
+  .boot2 ORIGIN(BOOT2) :
+  {
+    KEEP(*(.boot2));
+  } > BOOT2
+} INSERT BEFORE .text;
@@ -0,0 +1,112 @@
+#![no_main]
+#![no_std]
+
+pub mod meter;
+pub mod time;
+
+use defmt_rtt as _;
+use meter::{Meter, MeterPins};
+use panic_halt as _;
+use rp2040_hal::clocks::{init_clocks_and_plls, Clock};
+use rp2040_hal::gpio::{FunctionPio0, Pin, Pins};
+use rp2040_hal::{pac, Sio, Timer, Watchdog};
+use runtime::log::{self, info, Event};
+use runtime::settings::Settings;
+use runtime::{elapsed, queue, Message::*, State, TICK_MS};
+
+/// the second stage bootloader, for the flash on the pico
+#[link_section = ".boot2"]
+#[used]
+pub static BOOT2_FIRMWARE: [u8; 256] = rp2040_boot2::BOOT_LOADER_W25Q080;
+
+/// the crystal on the pico
+const XTAL_HZ: u32 = 12_000_000;
+
+defmt::timestamp!(
+    "{=u32:ms}",
+    time::now().ticks() / (runtime::TIMER_HZ / 1000)
+);
+
+#[rp2040_hal::entry]
+fn main() -> ! {
+    let mut device = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(device.WATCHDOG);
+    let clocks = init_clocks_and_plls(
+        XTAL_HZ,
+        device.XOSC,
+        device.CLOCKS,
+        device.PLL_SYS,
+        device.PLL_USB,
+        &mut device.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    // only started for `time::now`, which reads it directly
+    let _timer = Timer::new(device.TIMER, &mut device.RESETS, &clocks);
+
+    let sio = Sio::new(device.SIO);
+    let pins = Pins::new(
+        device.IO_BANK0,
+        device.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut device.RESETS,
+    );
+
+    // there's no rp2040 board yet, these are the pins the
+    // meter is wired to on a pico. the state machines find
+    // the pins by number, so they're only handed over
+    let register_data: Pin<_, FunctionPio0, _> = pins.gpio10.into_function();
+    let register_clock: Pin<_, FunctionPio0, _> = pins.gpio11.into_function();
+    let register_latch: Pin<_, FunctionPio0, _> = pins.gpio12.into_function();
+    let input_data: Pin<_, FunctionPio0, _> = pins.gpio2.into_function();
+    let _: Pin<_, FunctionPio0, _> = pins.gpio3.into_function();
+    let _: Pin<_, FunctionPio0, _> = pins.gpio4.into_function();
+
+    let mut meter = Meter::new(
+        device.PIO0,
+        &mut device.RESETS,
+        MeterPins {
+            register_data: register_data.id().num,
+            register_clock: register_clock.id().num,
+            register_latch: register_latch.id().num,
+            input_data: input_data.id().num,
+        },
+        clocks.system_clock.freq().to_Hz(),
+    );
+
+    info!("booted");
+
+    let mut state = State::Booting {
+        settings: Settings::default(),
+    };
+    let mut last_tick = time::now();
+
+    Booted.send();
+
+    // nothing else shares the core yet, so it polls
+    // instead of sleeping between messages
+    loop {
+        meter.read();
+        meter.clock();
+
+        if elapsed(last_tick, time::now()).to_millis() >= TICK_MS {
+            last_tick = time::now();
+
+            Tick.send();
+        }
+
+        if let Some(msg) = queue::next() {
+            let name = state.name();
+
+            state = state.recv(msg, time::now());
+
+            if state.name() != name {
+                log::event(Event::State(state.name()));
+            }
+
+            meter.write(&state);
+        }
+    }
+}
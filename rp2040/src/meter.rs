@@ -0,0 +1,259 @@
+use crate::time;
+use rp2040_hal::pac::{PIO0, RESETS};
+use rp2040_hal::pio::{
+    PIOBuilder, PIOExt, PinDir, Rx, ShiftDirection, Tx, UninitStateMachine, PIO, SM0, SM1,
+};
+use runtime::meter::{chain, fault, flash, intensities};
+use runtime::modulation::{Modulator, MIN_REFRESH_HZ, MODULATION_FRAMES};
+use runtime::{elapsed, Message::*, State, State::*, TimeInstant, METER_CHANNELS, METER_SEGMENTS};
+
+/// how many samples of the meter converter to take
+/// before sending the average to state, the same as the
+/// stm32 firmware so the meter moves the same
+const CLOCKS_PER_READ: u32 = 96 * 16;
+/// how long the input number is shown after switching
+const INPUT_FLASH_MS: u32 = 1000;
+
+/// the bits shifted out for each frame
+const FRAME_BITS: usize = METER_SEGMENTS * METER_CHANNELS;
+/// the words pushed for each frame, the bit count then
+/// the bits
+const FRAME_WORDS: usize = 1 + FRAME_BITS.div_ceil(32);
+/// the state machine's cycles for each frame, two for
+/// each bit, two to load the count and two to latch
+const FRAME_CYCLES: u32 = 2 * FRAME_BITS as u32 + 4;
+/// the rate the shift program runs at, slowed so a
+/// whole modulation cycle takes as long as on the stm32
+const SHIFT_HZ: u32 = MIN_REFRESH_HZ * MODULATION_FRAMES as u32 * FRAME_CYCLES;
+
+/// samples of every channel in each word read from the
+/// input program
+const SAMPLES_PER_WORD: usize = 32 / METER_CHANNELS;
+
+/// a whole frame has to fit in the fifo, which is only
+/// written once it's empty
+const _: () = assert!(FRAME_WORDS <= 4);
+
+/// the gpio numbers the meter is wired to
+pub struct MeterPins {
+    pub register_data: u8,
+    pub register_clock: u8,
+    pub register_latch: u8,
+    /// the first channel's data, the other channels
+    /// follow it, then the converter's clock
+    pub input_data: u8,
+}
+
+/// the meter's input and segments, each run by a state
+/// machine so the core only handles whole samples and
+/// frames
+pub struct Meter {
+    samples: Rx<(PIO0, SM1)>,
+    frames: Tx<(PIO0, SM0)>,
+    sample_count: u32,
+    counts: [u32; METER_CHANNELS],
+    modulator: Modulator<METER_SEGMENTS, METER_CHANNELS>,
+    audio_input: Option<u8>,
+    flash_start: Option<TimeInstant>,
+    self_tested: bool,
+}
+
+impl Meter {
+    pub fn new(pio: PIO0, resets: &mut RESETS, pins: MeterPins, system_hz: u32) -> Self {
+        let (mut pio, shift, sample, _, _) = pio.split(resets);
+
+        Self {
+            frames: shift_program(&mut pio, shift, &pins, system_hz),
+            samples: sample_program(&mut pio, sample, &pins),
+            sample_count: 0,
+            counts: [0; METER_CHANNELS],
+            modulator: Modulator::new(),
+            audio_input: None,
+            flash_start: None,
+            self_tested: false,
+        }
+    }
+
+    /// count the samples taken since the last read, and
+    /// send the levels once there are enough
+    pub fn read(&mut self) {
+        while let Some(word) = self.samples.read() {
+            for sample in 0..SAMPLES_PER_WORD {
+                let data = word >> (sample * METER_CHANNELS);
+
+                for (channel, count) in self.counts.iter_mut().enumerate() {
+                    if data >> channel & 1 == 1 {
+                        *count += 1;
+                    }
+                }
+            }
+
+            self.sample_count += SAMPLES_PER_WORD as u32;
+
+            if self.sample_count >= CLOCKS_PER_READ {
+                let mut levels = [0.0; METER_CHANNELS];
+
+                for (level, count) in levels.iter_mut().zip(self.counts.iter_mut()) {
+                    *level = *count as f32 / self.sample_count as f32;
+                    *count = 0;
+                }
+
+                MeterUpdate(levels).send();
+
+                self.sample_count = 0;
+            }
+        }
+    }
+
+    pub fn write(&mut self, state: &State) {
+        if let SelfTest { .. } = state {
+            // there's no sense input to find dead segments
+            // with, so the test passes straight away
+            if !self.self_tested {
+                self.self_tested = true;
+
+                SelfTestResult([0; METER_CHANNELS]).send();
+                SelfTestDone.send();
+            }
+
+            return;
+        }
+
+        self.self_tested = false;
+
+        if let Fault { .. } = state {
+            self.modulator.set(fault());
+
+            return;
+        }
+
+        if let Running { settings, .. } = state {
+            let audio_input = settings.audio_input;
+
+            // flash the input number when it changes, but
+            // not when it's first set
+            if self.audio_input.is_some() && self.audio_input != Some(audio_input) {
+                self.flash_start = Some(time::now());
+                self.modulator.set(flash(audio_input));
+            }
+
+            self.audio_input = Some(audio_input);
+        }
+
+        if self.flash_start.is_none() {
+            self.modulator.set(intensities(state));
+        }
+    }
+
+    /// hand the shift program the next frame once it's
+    /// taken the last one
+    pub fn clock(&mut self) {
+        if let Some(flash_start) = self.flash_start {
+            if elapsed(flash_start, time::now()).to_millis() >= INPUT_FLASH_MS {
+                self.flash_start = None;
+            }
+        }
+
+        if !self.frames.is_empty() {
+            return;
+        }
+
+        let lit = self.modulator.next_frame();
+        let mut words = [0; FRAME_WORDS];
+
+        words[0] = FRAME_BITS as u32 - 1;
+
+        for (index, bit) in chain(&lit).enumerate() {
+            if bit {
+                words[1 + index / 32] |= 1 << (31 - index % 32);
+            }
+        }
+
+        for word in words {
+            self.frames.write(word);
+        }
+    }
+}
+
+/// shifts each frame into the chain of shift registers
+/// then latches it, the clock is side-set alongside the
+/// data
+fn shift_program(
+    pio: &mut PIO<PIO0>,
+    sm: UninitStateMachine<(PIO0, SM0)>,
+    pins: &MeterPins,
+    system_hz: u32,
+) -> Tx<(PIO0, SM0)> {
+    let program = pio_proc::pio_asm!(
+        ".side_set 1",
+        ".wrap_target",
+        // drop what's left of the last frame's bits, then
+        // the count of bits in this one, less one
+        "    pull block     side 0",
+        "    out x, 32      side 0",
+        "bit:",
+        "    out pins, 1    side 0",
+        "    jmp x-- bit    side 1",
+        "    set pins, 1    side 0",
+        "    set pins, 0    side 0",
+        ".wrap",
+    );
+    let installed = pio.install(&program.program).unwrap();
+    let whole = system_hz / SHIFT_HZ;
+    let fraction = (system_hz % SHIFT_HZ) as u64 * 256 / SHIFT_HZ as u64;
+
+    let (mut sm, _, tx) = PIOBuilder::from_installed_program(installed)
+        .out_pins(pins.register_data, 1)
+        .side_set_pin_base(pins.register_clock)
+        .set_pins(pins.register_latch, 1)
+        .out_shift_direction(ShiftDirection::Left)
+        .autopull(true)
+        .pull_threshold(32)
+        .clock_divisor_fixed_point(whole as u16, fraction as u8)
+        .build(sm);
+
+    sm.set_pindirs([
+        (pins.register_data, PinDir::Output),
+        (pins.register_clock, PinDir::Output),
+        (pins.register_latch, PinDir::Output),
+    ]);
+    sm.start();
+
+    tx
+}
+
+/// samples every channel's data on both edges of the
+/// converter's clock, like the stm32's interrupt does
+fn sample_program(
+    pio: &mut PIO<PIO0>,
+    sm: UninitStateMachine<(PIO0, SM1)>,
+    pins: &MeterPins,
+) -> Rx<(PIO0, SM1)> {
+    // the clock's index from the first data pin depends
+    // on the channel count, so it's assembled here
+    let clock = METER_CHANNELS as u8;
+    let mut assembler = pio::Assembler::<32>::new();
+    let mut wrap_target = assembler.label();
+    let mut wrap_source = assembler.label();
+
+    assembler.bind(&mut wrap_target);
+    assembler.wait(1, pio::WaitSource::PIN, clock, false);
+    assembler.r#in(pio::InSource::PINS, METER_CHANNELS as u8);
+    assembler.wait(0, pio::WaitSource::PIN, clock, false);
+    assembler.r#in(pio::InSource::PINS, METER_CHANNELS as u8);
+    assembler.bind(&mut wrap_source);
+
+    let program = assembler.assemble_with_wrap(wrap_source, wrap_target);
+    let installed = pio.install(&program).unwrap();
+
+    let (sm, rx, _) = PIOBuilder::from_installed_program(installed)
+        .in_pin_base(pins.input_data)
+        .in_shift_direction(ShiftDirection::Left)
+        .autopush(true)
+        .push_threshold((SAMPLES_PER_WORD * METER_CHANNELS) as u8)
+        .build(sm);
+
+    sm.start();
+
+    rx
+}
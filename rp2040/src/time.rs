@@ -0,0 +1,16 @@
+use rp2040_hal::pac;
+use runtime::{TimeInstant, TIMER_HZ};
+
+/// the rate the rp2040's timer counts at
+const MICROS_HZ: u32 = 1_000_000;
+
+/// the time in the runtime's units, from the low word of
+/// the timer. it wraps the same way as the instants it's
+/// turned into, so they stay in step across the wrap
+pub fn now() -> TimeInstant {
+    // reading the raw word doesn't latch the high one, so
+    // it's safe from anywhere
+    let micros = unsafe { (*pac::TIMER::ptr()).timerawl().read().bits() };
+
+    TimeInstant::from_ticks(micros.wrapping_mul(TIMER_HZ / MICROS_HZ))
+}
@@ -0,0 +1,308 @@
+use crate::{TimeDuration, TimeInstant};
+use embedded_hal::{blocking::spi::Write, digital::v2::OutputPin};
+use fugit::ExtU32;
+
+/// the volume at unity gain, each step below it is
+/// another half db of attenuation down to zero, which
+/// attenuates fully
+pub const VOLUME_UNITY: u8 = 192;
+
+/// the volume for a gain in db, rounded to the nearest
+/// half db step
+pub fn volume_from_db(db: f32) -> Option<u8> {
+    let steps = db * 2.0 + VOLUME_UNITY as f32;
+
+    if !(0.0..=VOLUME_UNITY as f32).contains(&steps) {
+        return None;
+    }
+
+    Some((steps + 0.5) as u8)
+}
+
+/// the gain in db for a volume, with zero being fully
+/// attenuated
+pub fn volume_to_db(volume: u8) -> Option<f32> {
+    if volume == 0 {
+        return None;
+    }
+
+    Some((volume as f32 - VOLUME_UNITY as f32) / 2.0)
+}
+
+/// a pga2311 style stereo volume control.
+///
+/// the gain is sent as one byte per channel, with the
+/// chip's own code matching `volume` so no translation
+/// is needed. changes are ramped a half db step at a
+/// time so neither mute nor volume changes zipper.
+pub struct Attenuator<Spi, Cs> {
+    spi: Spi,
+    cs: Cs,
+    current: u8,
+    target: u8,
+    step: TimeDuration,
+    next_step: TimeInstant,
+}
+
+impl<Spi, Cs> Attenuator<Spi, Cs>
+where
+    Spi: Write<u8>,
+    Cs: OutputPin,
+{
+    pub fn new(spi: Spi, mut cs: Cs) -> Self {
+        cs.set_high().ok();
+
+        let mut attenuator = Self {
+            spi,
+            cs,
+            current: 0,
+            target: 0,
+            step: TimeDuration::from_ticks(0),
+            next_step: TimeInstant::from_ticks(0),
+        };
+
+        attenuator.send();
+        attenuator
+    }
+
+    /// whether the output has been ramped all the way
+    /// down
+    pub fn is_silent(&self) -> bool {
+        self.current == 0
+    }
+
+    /// start ramping towards `volume`, taking `ramp_ms`
+    /// to cover the whole range
+    pub fn ramp_to(&mut self, volume: u8, ramp_ms: u16, now: TimeInstant) {
+        if self.current == self.target {
+            self.next_step = now;
+        }
+
+        self.target = volume.min(VOLUME_UNITY);
+        self.step = (ramp_ms as u32 * 1000 / VOLUME_UNITY as u32).micros();
+    }
+
+    pub fn clock(&mut self, now: TimeInstant) {
+        if self.current == self.target || now < self.next_step {
+            return;
+        }
+
+        if self.current < self.target {
+            self.current += 1;
+        } else {
+            self.current -= 1;
+        }
+
+        self.next_step = now + self.step;
+        self.send();
+    }
+
+    fn send(&mut self) {
+        self.cs.set_low().ok();
+        self.spi.write(&[self.current, self.current]).ok();
+        self.cs.set_high().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Bus {
+        Select(bool),
+        Write(Vec<u8>),
+    }
+
+    /// what went out on the bus and the chip select, in
+    /// order
+    type Log = Rc<RefCell<Vec<Bus>>>;
+
+    struct MockSpi(Log);
+
+    impl Write<u8> for MockSpi {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Bus::Write(words.to_vec()));
+
+            Ok(())
+        }
+    }
+
+    struct MockCs(Log);
+
+    impl OutputPin for MockCs {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Bus::Select(true));
+
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Bus::Select(false));
+
+            Ok(())
+        }
+    }
+
+    fn attenuator() -> (Attenuator<MockSpi, MockCs>, Log) {
+        let log = Log::default();
+        let attenuator = Attenuator::new(MockSpi(log.clone()), MockCs(log.clone()));
+
+        (attenuator, log)
+    }
+
+    /// the volumes sent since the log was last taken
+    fn sent(log: &Log) -> Vec<u8> {
+        log.take()
+            .into_iter()
+            .filter_map(|bus| match bus {
+                Bus::Write(words) => {
+                    assert_eq!(words[0], words[1], "channels differ");
+
+                    Some(words[0])
+                }
+                Bus::Select(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn starts_fully_attenuated() {
+        let (attenuator, log) = attenuator();
+
+        assert!(attenuator.is_silent());
+        assert_eq!(
+            log.take(),
+            [
+                Bus::Select(false),
+                Bus::Select(true),
+                Bus::Write(vec![0, 0]),
+                Bus::Select(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn ramps_a_step_at_a_time() {
+        let (mut attenuator, log) = attenuator();
+        let start = TimeInstant::from_ticks(0);
+
+        log.take();
+
+        // the whole range in 192ms is a step a millisecond
+        attenuator.ramp_to(10, 192, start);
+
+        for ms in 0..20 {
+            let now = start + ms.millis();
+
+            attenuator.clock(now);
+            attenuator.clock(now + 500.micros());
+        }
+
+        assert_eq!(sent(&log), (1..=10).collect::<Vec<_>>());
+        assert!(!attenuator.is_silent());
+    }
+
+    #[test]
+    fn every_write_is_framed_by_the_chip_select() {
+        let (mut attenuator, log) = attenuator();
+        let start = TimeInstant::from_ticks(0);
+
+        log.take();
+        attenuator.ramp_to(2, 0, start);
+        attenuator.clock(start);
+
+        assert_eq!(
+            log.take(),
+            [
+                Bus::Select(true),
+                Bus::Write(vec![1, 1]),
+                Bus::Select(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn mute_ramps_down_to_silence() {
+        let (mut attenuator, log) = attenuator();
+        let start = TimeInstant::from_ticks(0);
+
+        attenuator.ramp_to(VOLUME_UNITY, 0, start);
+
+        for _ in 0..VOLUME_UNITY {
+            attenuator.clock(start);
+        }
+
+        log.take();
+        attenuator.ramp_to(0, 0, start);
+
+        for _ in 0..VOLUME_UNITY - 1 {
+            attenuator.clock(start);
+        }
+
+        assert!(!attenuator.is_silent());
+
+        attenuator.clock(start);
+
+        assert!(attenuator.is_silent());
+        assert_eq!(sent(&log), (0..VOLUME_UNITY).rev().collect::<Vec<_>>());
+
+        // nothing more once it's there
+        attenuator.clock(start);
+
+        assert_eq!(sent(&log), []);
+    }
+
+    #[test]
+    fn turning_round_part_way_keeps_going_from_where_it_got_to() {
+        let (mut attenuator, log) = attenuator();
+        let start = TimeInstant::from_ticks(0);
+
+        attenuator.ramp_to(100, 0, start);
+
+        for _ in 0..5 {
+            attenuator.clock(start);
+        }
+
+        attenuator.ramp_to(0, 0, start);
+
+        for _ in 0..10 {
+            attenuator.clock(start);
+        }
+
+        assert_eq!(sent(&log), [0, 1, 2, 3, 4, 5, 4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn volume_is_capped_at_unity() {
+        let (mut attenuator, log) = attenuator();
+        let start = TimeInstant::from_ticks(0);
+
+        log.take();
+        attenuator.ramp_to(u8::MAX, 0, start);
+
+        for _ in 0..u8::MAX {
+            attenuator.clock(start);
+        }
+
+        assert_eq!(sent(&log).last(), Some(&VOLUME_UNITY));
+    }
+
+    #[test]
+    fn volumes_convert_to_and_from_db() {
+        assert_eq!(volume_to_db(VOLUME_UNITY), Some(0.0));
+        assert_eq!(volume_to_db(VOLUME_UNITY - 13), Some(-6.5));
+        assert_eq!(volume_to_db(0), None);
+        assert_eq!(volume_from_db(0.0), Some(VOLUME_UNITY));
+        assert_eq!(volume_from_db(-6.4), Some(VOLUME_UNITY - 13));
+        assert_eq!(volume_from_db(0.5), None);
+        assert_eq!(volume_from_db(-97.0), None);
+    }
+}
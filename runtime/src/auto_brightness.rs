@@ -0,0 +1,222 @@
+/// the number of points on the brightness curve
+pub const CURVE_POINTS: usize = 4;
+/// how much of the difference between the smoothed
+/// and the new reading is taken on each update
+const SMOOTHING: f32 = 0.1;
+/// how far the curve has to move away from the
+/// current brightness before it's followed
+const HYSTERESIS: u8 = 5;
+
+/// a source of ambient light readings
+pub trait LightSensor {
+    /// the current ambient light in lux, or none if no
+    /// reading is available
+    fn lux(&mut self) -> Option<f32>;
+}
+
+/// maps ambient light onto brightness.
+///
+/// readings are smoothed, then looked up on a curve of
+/// `(lux, percent)` points with linear interpolation
+/// between them. the output only follows the curve once
+/// it has moved further than the hysteresis band, so the
+/// leds don't hunt around a threshold.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoBrightness {
+    /// `(lux, percent)` points in order of rising lux
+    pub curve: [(f32, u8); CURVE_POINTS],
+    smoothed: Option<f32>,
+    output: u8,
+}
+
+impl AutoBrightness {
+    /// forget the smoothed reading so the next update
+    /// is followed straight away
+    pub fn reset(&mut self) {
+        self.smoothed = None;
+    }
+
+    /// the brightness last returned by `update`
+    pub fn output(&self) -> u8 {
+        self.output
+    }
+
+    /// move a point on the curve, keeping the points in
+    /// order of rising lux
+    pub fn set_point(&mut self, index: usize, lux: f32, percent: u8) {
+        if let Some(point) = self.curve.get_mut(index) {
+            *point = (lux, percent.min(100));
+        }
+
+        self.curve.sort_unstable_by(|(a, _), (b, _)| {
+            a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Equal)
+        });
+    }
+
+    /// take a reading and return the brightness to use
+    pub fn update(&mut self, lux: f32) -> u8 {
+        let smoothed = match self.smoothed {
+            Some(smoothed) => smoothed + (lux - smoothed) * SMOOTHING,
+            None => {
+                self.output = self.lookup(lux);
+
+                lux
+            }
+        };
+
+        self.smoothed = Some(smoothed);
+
+        let target = self.lookup(smoothed);
+
+        if target.abs_diff(self.output) >= HYSTERESIS {
+            self.output = target;
+        }
+
+        self.output
+    }
+
+    /// the brightness on the curve for `lux`
+    pub fn lookup(&self, lux: f32) -> u8 {
+        let (first_lux, first_percent) = self.curve[0];
+        let (last_lux, last_percent) = self.curve[CURVE_POINTS - 1];
+
+        if lux <= first_lux {
+            return first_percent;
+        }
+
+        if lux >= last_lux {
+            return last_percent;
+        }
+
+        for window in self.curve.windows(2) {
+            let (lower_lux, lower_percent) = window[0];
+            let (upper_lux, upper_percent) = window[1];
+
+            if lux <= upper_lux && upper_lux > lower_lux {
+                let fraction = (lux - lower_lux) / (upper_lux - lower_lux);
+                let lower = lower_percent as f32;
+                let upper = upper_percent as f32;
+
+                return (lower + (upper - lower) * fraction) as u8;
+            }
+        }
+
+        last_percent
+    }
+}
+
+impl Default for AutoBrightness {
+    fn default() -> Self {
+        Self {
+            curve: [(1.0, 5), (10.0, 30), (100.0, 65), (1000.0, 100)],
+            smoothed: None,
+            output: 100,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a lit room read every update, with the sensor's
+    /// noise and the mains flicker it picks up
+    const STEADY_ROOM: [f32; 24] = [
+        52.0, 47.5, 55.1, 49.0, 43.2, 58.7, 50.3, 46.1, 53.9, 57.2, 41.8, 50.0, 48.6, 54.4, 45.3,
+        59.1, 51.7, 44.0, 56.3, 49.9, 42.6, 53.0, 47.8, 55.6,
+    ];
+
+    /// a sensor playing back a recorded trace, with no
+    /// reading once it runs out
+    struct Trace<Readings>(Readings);
+
+    impl<Readings> LightSensor for Trace<Readings>
+    where
+        Readings: Iterator<Item = f32>,
+    {
+        fn lux(&mut self) -> Option<f32> {
+            self.0.next()
+        }
+    }
+
+    /// read `trace` through the sensor until it runs out,
+    /// returning the brightness after each reading
+    fn run(auto: &mut AutoBrightness, trace: impl IntoIterator<Item = f32>) -> Vec<u8> {
+        let mut sensor = Trace(trace.into_iter());
+
+        core::iter::from_fn(|| sensor.lux())
+            .map(|lux| auto.update(lux))
+            .collect()
+    }
+
+    #[test]
+    fn lookup_follows_the_curve() {
+        let auto = AutoBrightness::default();
+
+        assert_eq!(auto.lookup(0.0), 5);
+        assert_eq!(auto.lookup(1.0), 5);
+        assert_eq!(auto.lookup(10.0), 30);
+        assert_eq!(auto.lookup(55.0), 47);
+        assert_eq!(auto.lookup(1000.0), 100);
+        assert_eq!(auto.lookup(50_000.0), 100);
+    }
+
+    #[test]
+    fn first_reading_is_followed_straight_away() {
+        let mut auto = AutoBrightness::default();
+
+        assert_eq!(auto.update(10.0), 30);
+
+        auto.reset();
+
+        assert_eq!(auto.update(1000.0), 100);
+    }
+
+    #[test]
+    fn noise_does_not_hunt() {
+        let mut auto = AutoBrightness::default();
+        let outputs = run(&mut auto, STEADY_ROOM);
+
+        assert!(outputs.iter().all(|output| *output == outputs[0]));
+    }
+
+    #[test]
+    fn lamp_switched_on_is_followed_smoothly() {
+        let mut auto = AutoBrightness::default();
+        let trace = [5.0; 10].into_iter().chain([500.0; 60]);
+        let outputs = run(&mut auto, trace);
+
+        assert_eq!(outputs[9], auto.lookup(5.0));
+        // no jump straight to the new level, then steps no
+        // smaller than the hysteresis up to it
+        assert!(outputs[10] < 50);
+        assert!(outputs.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!(outputs
+            .windows(2)
+            .all(|pair| pair[1] == pair[0] || pair[1] - pair[0] >= HYSTERESIS));
+        assert!(outputs[69].abs_diff(auto.lookup(500.0)) < HYSTERESIS);
+    }
+
+    #[test]
+    fn dusk_dims_down() {
+        let mut auto = AutoBrightness::default();
+        let trace = (0..120)
+            .map(|step| 800.0 * 0.95_f32.powi(step))
+            .chain([1.0; 40]);
+        let outputs = run(&mut auto, trace);
+
+        assert_eq!(outputs[0], auto.lookup(800.0));
+        assert!(outputs.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(outputs[159] < auto.lookup(1.0) + HYSTERESIS);
+    }
+
+    #[test]
+    fn points_stay_in_order() {
+        let mut auto = AutoBrightness::default();
+
+        auto.set_point(0, 2000.0, 150);
+
+        assert_eq!(auto.curve[CURVE_POINTS - 1], (2000.0, 100));
+        assert_eq!(auto.curve[0], (10.0, 30));
+    }
+}
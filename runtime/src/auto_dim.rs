@@ -0,0 +1,172 @@
+use crate::{elapsed, TimeInstant, DB_MINUS_36};
+
+/// meter input at or above which counts as activity
+pub const ACTIVITY_LEVEL: f32 = DB_MINUS_36;
+
+/// dims the leds after a while without key presses or
+/// signal on the meters.
+///
+/// the monotonic timer wraps every few minutes, so idle
+/// time is accumulated from the gaps between updates
+/// rather than measured from the last activity.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoDim {
+    /// minutes without activity before dimming, zero
+    /// turns auto dim off
+    pub timeout_minutes: u8,
+    /// brightness to dim down to
+    pub floor: u8,
+    idle_ms: u32,
+    last_update: TimeInstant,
+    dimmed: bool,
+}
+
+impl AutoDim {
+    pub fn new(now: TimeInstant) -> Self {
+        Self {
+            timeout_minutes: 0,
+            floor: 10,
+            idle_ms: 0,
+            last_update: now,
+            dimmed: false,
+        }
+    }
+
+    pub fn is_dimmed(&self) -> bool {
+        self.dimmed
+    }
+
+    /// how long it's been without activity, as of the
+    /// last update
+    pub fn idle_ms(&self) -> u32 {
+        self.idle_ms
+    }
+
+    /// restart the idle timer, returns true if this woke
+    /// the leds up
+    pub fn activity(&mut self, now: TimeInstant) -> bool {
+        self.update(now);
+        self.idle_ms = 0;
+
+        core::mem::replace(&mut self.dimmed, false)
+    }
+
+    /// advance the idle timer, returns true if this
+    /// dimmed the leds
+    pub fn update(&mut self, now: TimeInstant) -> bool {
+        let elapsed = elapsed(self.last_update, now);
+
+        self.last_update = now;
+        self.idle_ms = self.idle_ms.saturating_add(elapsed.to_millis());
+
+        if !self.dimmed
+            && self.timeout_minutes > 0
+            && self.idle_ms >= self.timeout_minutes as u32 * 60_000
+        {
+            self.dimmed = true;
+
+            return true;
+        }
+
+        false
+    }
+
+    /// the brightness to show for the chosen `brightness`
+    pub fn brightness(&self, brightness: u8) -> u8 {
+        if self.dimmed {
+            brightness.min(self.floor)
+        } else {
+            brightness
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fugit::ExtU32;
+
+    /// an auto dim started at `start` that times out
+    /// after a minute
+    fn one_minute(start: TimeInstant) -> AutoDim {
+        let mut auto_dim = AutoDim::new(start);
+
+        auto_dim.timeout_minutes = 1;
+        auto_dim
+    }
+
+    #[test]
+    fn dims_once_the_timeout_passes() {
+        let start = TimeInstant::from_ticks(0);
+        let mut auto_dim = one_minute(start);
+
+        for second in 1..60 {
+            assert!(!auto_dim.update(start + (second * 1000).millis()));
+        }
+
+        assert!(auto_dim.update(start + 60.secs()));
+        assert!(auto_dim.is_dimmed());
+        // only the update that dims says so
+        assert!(!auto_dim.update(start + 61.secs()));
+        assert!(auto_dim.is_dimmed());
+    }
+
+    #[test]
+    fn activity_restarts_the_timeout() {
+        let start = TimeInstant::from_ticks(0);
+        let mut auto_dim = one_minute(start);
+
+        assert!(!auto_dim.update(start + 50.secs()));
+        assert!(!auto_dim.activity(start + 55.secs()));
+        assert!(!auto_dim.update(start + 110.secs()));
+        assert!(auto_dim.update(start + 115.secs()));
+    }
+
+    #[test]
+    fn activity_wakes_the_leds() {
+        let start = TimeInstant::from_ticks(0);
+        let mut auto_dim = one_minute(start);
+
+        auto_dim.update(start + 60.secs());
+
+        assert!(auto_dim.activity(start + 61.secs()));
+        assert!(!auto_dim.is_dimmed());
+        assert!(!auto_dim.activity(start + 62.secs()));
+    }
+
+    #[test]
+    fn never_dims_when_off() {
+        let mut now = TimeInstant::from_ticks(0);
+        let mut auto_dim = AutoDim::new(now);
+
+        // two hours, wrapping the timer many times over
+        for _ in 0..240 {
+            now += 30.secs();
+
+            assert!(!auto_dim.update(now));
+        }
+    }
+
+    #[test]
+    fn idle_time_carries_across_the_timer_wrapping() {
+        // the timer wraps a little under every nine minutes
+        let start = TimeInstant::from_ticks(u32::MAX - 8_000_000 * 30);
+        let mut auto_dim = one_minute(start);
+
+        assert!(!auto_dim.update(start + 30.secs()));
+        assert!(auto_dim.update(start + 60.secs()));
+    }
+
+    #[test]
+    fn dimmed_brightness_is_capped_at_the_floor() {
+        let start = TimeInstant::from_ticks(0);
+        let mut auto_dim = one_minute(start);
+
+        assert_eq!(auto_dim.brightness(80), 80);
+
+        auto_dim.update(start + 60.secs());
+
+        assert_eq!(auto_dim.brightness(80), auto_dim.floor);
+        assert_eq!(auto_dim.brightness(5), 5);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::attenuator::volume_from_db;
+use crate::auto_brightness::CURVE_POINTS;
+use crate::control::AUDIO_INPUTS;
+use crate::dsp::DSP_PRESETS;
+use crate::key::Key;
+use crate::log::Level;
+use crate::settings::{BootSequence, BrightnessLevel};
+use crate::Message::{self, *};
+
+/// a line received over the control interface
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    /// forward a message to the state
+    Send(Message),
+    /// report the current state back
+    Status,
+    /// report the meter channels back
+    Meter,
+    /// report the dsp's status registers back
+    DspStatus,
+    /// report how the message queues have coped
+    QueueStatus,
+    /// report the diagnostics counters
+    Diagnostics,
+    /// report them every so many seconds, zero for off
+    Summary(u32),
+    /// only log at or above a level
+    LogLevel(Level),
+    /// copy logged events to the control interface
+    LogMirror(bool),
+    /// report why the unit last reset
+    ResetCause,
+    /// report the crash logged before the last reset
+    Crash,
+    ClearCrash,
+}
+
+/// parse a single line of the control protocol.
+///
+/// words are separated by whitespace, anything that
+/// isn't an exact match, including trailing words, is
+/// rejected.
+pub fn parse(line: &str) -> Option<Command> {
+    let mut words = line.split_whitespace();
+
+    let command = match words.next()? {
+        "status" => Command::Status,
+        "meter" => Command::Meter,
+        "led-test" => Command::Send(RunSelfTest),
+        "key" => {
+            let key = key(words.next()?)?;
+
+            Command::Send(match words.next() {
+                Some("hold") => KeypadHold(key),
+                Some(_) => return None,
+                None => KeypadUpdate(key),
+            })
+        }
+        "mute" => Command::Send(SetMute(on_off(words.next()?)?)),
+        "peaks" => Command::Send(SetPeaks(on_off(words.next()?)?)),
+        "levels" => Command::Send(SetLevels(on_off(words.next()?)?)),
+        "brightness" => Command::Send(SetBrightness(match words.next()? {
+            "high" => BrightnessLevel::High.percent(),
+            "medium" => BrightnessLevel::Medium.percent(),
+            "low" => BrightnessLevel::Low.percent(),
+            value => percent(value)?,
+        })),
+        "auto-brightness" => Command::Send(match words.next()? {
+            value @ ("on" | "off") => SetAutoBrightness(on_off(value)?),
+            point => SetAutoBrightnessPoint(
+                point.parse().ok().filter(|point| *point < CURVE_POINTS)?,
+                words.next()?.parse().ok().filter(|lux: &f32| *lux >= 0.0)?,
+                percent(words.next()?)?,
+            ),
+        }),
+        "auto-dim" => Command::Send(match words.next()? {
+            "off" => SetAutoDim(0, 0),
+            minutes => SetAutoDim(
+                minutes.parse().ok().filter(|minutes| *minutes > 0)?,
+                percent(words.next()?)?,
+            ),
+        }),
+        "volume" => Command::Send(SetVolume(volume_from_db(words.next()?.parse().ok()?)?)),
+        "mute-ramp" => Command::Send(SetMuteRamp(words.next()?.parse().ok()?)),
+        "headphone-detect" => Command::Send(SetHeadphoneDetect(on_off(words.next()?)?)),
+        "input" => Command::Send(SetInput(
+            words
+                .next()?
+                .parse::<u8>()
+                .ok()
+                .filter(|input| (1..=AUDIO_INPUTS).contains(input))?
+                - 1,
+        )),
+        "preset" => Command::Send(SetDspPreset(
+            words
+                .next()?
+                .parse::<u8>()
+                .ok()
+                .filter(|preset| (1..=DSP_PRESETS).contains(preset))?
+                - 1,
+        )),
+        "dsp-volume" => Command::Send(SetDspVolume(volume_from_db(words.next()?.parse().ok()?)?)),
+        "dsp" => Command::DspStatus,
+        "reset-cause" => Command::ResetCause,
+        "queue" => Command::QueueStatus,
+        "log" => match words.next()? {
+            "mirror" => Command::LogMirror(on_off(words.next()?)?),
+            level => Command::LogLevel(Level::from_name(level)?),
+        },
+        "diagnostics" => match words.next() {
+            Some("every") => Command::Summary(words.next()?.parse().ok().filter(|s| *s > 0)?),
+            Some("off") => Command::Summary(0),
+            Some(_) => return None,
+            None => Command::Diagnostics,
+        },
+        "crash" => match words.next() {
+            Some("clear") => Command::ClearCrash,
+            Some(_) => return None,
+            None => Command::Crash,
+        },
+        "boot-sequence" => Command::Send(SetBootSequence(match words.next()? {
+            "off" => BootSequence::Off,
+            "sweep" => BootSequence::Sweep,
+            "full" => BootSequence::Full,
+            _ => return None,
+        })),
+        _ => return None,
+    };
+
+    if words.next().is_some() {
+        return None;
+    }
+
+    Some(command)
+}
+
+fn key(name: &str) -> Option<Key> {
+    match name {
+        "mute" => Some(Key::ToggleMute),
+        "output" => Some(Key::ToggleOutput),
+        "brightness-up" => Some(Key::BrightnessUp),
+        "brightness-down" => Some(Key::BrightnessDown),
+        "brightness" => Some(Key::ToggleBrightness),
+        "peaks" => Some(Key::TogglePeaks),
+        "levels" => Some(Key::ToggleLevels),
+        "input" => Some(Key::CycleInput),
+        _ => None,
+    }
+}
+
+fn on_off(value: &str) -> Option<bool> {
+    match value {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn percent(value: &str) -> Option<u8> {
+    value.parse().ok().filter(|percent| *percent <= 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_messages() {
+        assert!(matches!(
+            parse("mute on"),
+            Some(Command::Send(SetMute(true)))
+        ));
+        assert!(matches!(
+            parse("key peaks hold"),
+            Some(Command::Send(KeypadHold(Key::TogglePeaks)))
+        ));
+        assert!(matches!(
+            parse("brightness medium"),
+            Some(Command::Send(SetBrightness(65)))
+        ));
+        assert!(matches!(
+            parse("auto-brightness 2 150.5 40"),
+            Some(Command::Send(SetAutoBrightnessPoint(2, lux, 40))) if lux == 150.5
+        ));
+        assert!(matches!(
+            parse("boot-sequence sweep"),
+            Some(Command::Send(SetBootSequence(BootSequence::Sweep)))
+        ));
+    }
+
+    #[test]
+    fn inputs_and_presets_count_from_one() {
+        assert!(matches!(parse("input 1"), Some(Command::Send(SetInput(0)))));
+        assert!(parse("input 0").is_none());
+        assert!(parse(&std::format!("input {}", AUDIO_INPUTS + 1)).is_none());
+        assert!(matches!(
+            parse(&std::format!("preset {}", DSP_PRESETS)),
+            Some(Command::Send(SetDspPreset(preset))) if preset == DSP_PRESETS - 1
+        ));
+    }
+
+    #[test]
+    fn parses_reports() {
+        assert!(matches!(parse("status"), Some(Command::Status)));
+        assert!(matches!(parse("  queue  "), Some(Command::QueueStatus)));
+        assert!(matches!(parse("diagnostics"), Some(Command::Diagnostics)));
+        assert!(matches!(
+            parse("diagnostics every 10"),
+            Some(Command::Summary(10))
+        ));
+        assert!(matches!(
+            parse("diagnostics off"),
+            Some(Command::Summary(0))
+        ));
+        assert!(matches!(parse("crash clear"), Some(Command::ClearCrash)));
+        assert!(matches!(
+            parse("log warn"),
+            Some(Command::LogLevel(Level::Warn))
+        ));
+        assert!(matches!(
+            parse("log mirror on"),
+            Some(Command::LogMirror(true))
+        ));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for line in [
+            "",
+            "reboot",
+            "mute",
+            "mute maybe",
+            "mute on now",
+            "brightness 101",
+            "key volume",
+            "key mute twice",
+            "auto-brightness 9 10 10",
+            "auto-brightness 0 -1 10",
+            "auto-dim 0 10",
+            "diagnostics every 0",
+            "crash now",
+            "log loud",
+        ] {
+            assert!(parse(line).is_none(), "{:?}", line);
+        }
+    }
+}
@@ -0,0 +1,773 @@
+use crate::attenuator::Attenuator;
+use crate::dsp::{Dsp, DspStatus};
+use crate::{elapsed, State, State::*, TimeInstant};
+use embedded_hal::{blocking::spi::Write, digital::v2::OutputPin};
+use fugit::ExtU32;
+
+/// the number of inputs the mux selects between
+pub const AUDIO_INPUTS: u8 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioOutput {
+    Headphones,
+    Speakers,
+}
+
+/// how long the mute is given to settle before the
+/// output is switched
+pub const MUTE_SETTLE_MS: u32 = 50;
+/// how long the relays and dsp are given to settle
+/// before the mute is restored
+pub const SWITCH_SETTLE_MS: u32 = 100;
+/// how long the speaker relay is held off after boot
+/// or a fault, letting the amplifier settle
+pub const SPEAKER_DELAY_MS: u32 = 3000;
+
+/// a step of the switch sequence
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwitchAction {
+    /// switch the relays, dsp and input mux over
+    Switch,
+    /// let go of the mute again
+    Restore,
+}
+
+/// sequences switching outputs or inputs so it happens
+/// behind the mute.
+///
+/// the mute is asserted as soon as a switch starts,
+/// then the switch is made once the mute has settled,
+/// and the mute is restored once the relays have
+/// settled. starting another switch part way through
+/// goes back to waiting on the mute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwitchSequence {
+    Idle,
+    Muting { until: TimeInstant },
+    Switching { until: TimeInstant },
+}
+
+impl SwitchSequence {
+    pub fn is_idle(&self) -> bool {
+        matches!(self, SwitchSequence::Idle)
+    }
+
+    pub fn start(&mut self, now: TimeInstant) {
+        *self = SwitchSequence::Muting {
+            until: now + MUTE_SETTLE_MS.millis(),
+        };
+    }
+
+    /// advance the sequence, returning the step to take
+    /// once it is due
+    pub fn clock(&mut self, now: TimeInstant) -> Option<SwitchAction> {
+        use SwitchSequence::*;
+
+        match *self {
+            Muting { until } if now >= until => {
+                *self = Switching {
+                    until: now + SWITCH_SETTLE_MS.millis(),
+                };
+
+                Some(SwitchAction::Switch)
+            }
+            Switching { until } if now >= until => {
+                *self = Idle;
+
+                Some(SwitchAction::Restore)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// the lines the audio is switched with, which know
+/// the level each of them is active at on the board
+pub trait ControlPins {
+    /// pull the output relay over to the speakers, or let
+    /// it fall back to the headphones
+    fn set_speakers(&mut self, speakers: bool);
+
+    /// hold the output's hard mute
+    fn set_mute(&mut self, mute: bool);
+
+    /// select one of the mux's inputs
+    fn set_input(&mut self, input: u8);
+}
+
+/// a write control wants made to the dsp. the dsp is on
+/// a blocking bus, so control only asks for them and
+/// they're made from idle rather than the clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DspWrite {
+    Select(AudioOutput, u8),
+    Volume(u8),
+}
+
+impl DspWrite {
+    /// make the write, then read the status back
+    pub fn apply<D: Dsp>(self, dsp: &mut D) -> Option<DspStatus> {
+        match self {
+            DspWrite::Select(output, preset) => dsp.select(output, preset).ok()?,
+            DspWrite::Volume(volume) => dsp.set_volume(volume).ok()?,
+        }
+
+        dsp.status().ok()
+    }
+}
+
+/// holds the speaker relay off until the amplifier has
+/// had time to settle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SpeakerProtection {
+    Holding { since: Option<TimeInstant> },
+    Ready,
+}
+
+/// drives the attenuator, relays and mux to match the
+/// state, and asks for the dsp writes to go with them
+pub struct Control<P, Spi, Cs> {
+    pins: P,
+    audio_volume: Attenuator<Spi, Cs>,
+    audio_output: AudioOutput,
+    audio_input: u8,
+    audio_mute: bool,
+    dsp_presets: [u8; 2],
+    dsp_volume: u8,
+    dsp_select: Option<(AudioOutput, u8)>,
+    dsp_volume_write: Option<u8>,
+    dsp_status: Option<DspStatus>,
+    faulted: bool,
+    standby: bool,
+    protection: SpeakerProtection,
+    switch: SwitchSequence,
+}
+
+impl<P, Spi, Cs> Control<P, Spi, Cs>
+where
+    P: ControlPins,
+    Spi: Write<u8>,
+    Cs: OutputPin,
+{
+    pub fn new(pins: P, audio_volume: Attenuator<Spi, Cs>) -> Self {
+        // the pins start low, which is the first input to
+        // the headphones unmuted, and the switch starts
+        // due so the dsp is brought in line on the first
+        // clock
+        Self {
+            pins,
+            audio_volume,
+            audio_output: AudioOutput::Headphones,
+            audio_input: 0,
+            audio_mute: false,
+            dsp_presets: [0; 2],
+            dsp_volume: 0,
+            dsp_select: None,
+            dsp_volume_write: None,
+            dsp_status: None,
+            faulted: false,
+            standby: false,
+            protection: SpeakerProtection::Holding { since: None },
+            switch: SwitchSequence::Muting {
+                until: TimeInstant::from_ticks(0),
+            },
+        }
+    }
+
+    pub fn write(&mut self, state: &State, now: TimeInstant) {
+        match state {
+            Running { settings, .. } => {
+                self.faulted = false;
+                self.standby = false;
+                self.audio_mute = settings.audio_mute;
+
+                if self.audio_output != settings.audio_output
+                    || self.audio_input != settings.audio_input
+                    || self.dsp_presets != settings.dsp_presets
+                {
+                    self.audio_output = settings.audio_output;
+                    self.audio_input = settings.audio_input;
+                    self.dsp_presets = settings.dsp_presets;
+                    self.switch.start(now);
+                }
+
+                if self.dsp_volume != settings.dsp_volume {
+                    self.dsp_volume = settings.dsp_volume;
+                    self.dsp_volume_write = Some(settings.dsp_volume);
+                }
+
+                self.audio_volume.ramp_to(
+                    if settings.audio_mute {
+                        0
+                    } else {
+                        settings.audio_volume
+                    },
+                    settings.mute_ramp_ms,
+                    now,
+                );
+
+                self.update_mute();
+            }
+
+            // get off the speakers straight away rather
+            // than sequencing it, and hold them off again
+            // once the fault has gone. the dsp is on a
+            // blocking bus, so this is only done on the way
+            // into the fault rather than for every message
+            // handled in it
+            Fault { .. } if !self.faulted => {
+                self.faulted = true;
+                self.audio_mute = true;
+                self.audio_output = AudioOutput::Headphones;
+                self.protection = SpeakerProtection::Holding { since: None };
+                self.switch = SwitchSequence::Idle;
+
+                self.set_output(AudioOutput::Headphones);
+                self.audio_volume.ramp_to(0, 0, now);
+                self.update_mute();
+            }
+
+            // mute straight away, the core is stopped long
+            // before a ramp would finish
+            Standby { .. } => {
+                self.standby = true;
+                self.audio_volume.ramp_to(0, 0, now);
+                self.update_mute();
+            }
+
+            _ => {}
+        }
+    }
+
+    pub fn clock(&mut self, now: TimeInstant) {
+        if let SpeakerProtection::Holding { since } = self.protection {
+            match since {
+                None => self.protection = SpeakerProtection::Holding { since: Some(now) },
+                Some(since) if elapsed(since, now).to_millis() >= SPEAKER_DELAY_MS => {
+                    self.protection = SpeakerProtection::Ready;
+
+                    if self.audio_output == AudioOutput::Speakers {
+                        self.switch.start(now);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(SwitchAction::Switch) = self.switch.clock(now) {
+            self.set_output(self.audio_output);
+            self.pins.set_input(self.audio_input);
+        }
+
+        self.audio_volume.clock(now);
+        self.update_mute();
+    }
+
+    /// the next write the dsp is waiting on, the output is
+    /// selected before the volume is set
+    pub fn dsp_write(&self) -> Option<DspWrite> {
+        self.dsp_select
+            .map(|(output, preset)| DspWrite::Select(output, preset))
+            .or(self.dsp_volume_write.map(DspWrite::Volume))
+    }
+
+    /// note a write has been made along with the status
+    /// read back after it. a newer write asked for in the
+    /// meantime is left waiting
+    pub fn dsp_written(&mut self, write: DspWrite, status: Option<DspStatus>) {
+        match write {
+            DspWrite::Select(output, preset) if self.dsp_select == Some((output, preset)) => {
+                self.dsp_select = None;
+            }
+            DspWrite::Volume(volume) if self.dsp_volume_write == Some(volume) => {
+                self.dsp_volume_write = None;
+            }
+            _ => {}
+        }
+
+        self.dsp_status = status;
+    }
+
+    /// the status read back after the last dsp write, as
+    /// the bus is too slow to read it on demand
+    pub fn dsp_status(&self) -> Option<DspStatus> {
+        self.dsp_status
+    }
+
+    /// the hard mute is held on a fault, in standby, while
+    /// switching or the dsp is yet to be switched, and once a soft mute has ramped all the
+    /// way down
+    fn update_mute(&mut self) {
+        let audio_mute = self.faulted
+            || self.standby
+            || !self.switch.is_idle()
+            || self.dsp_select.is_some()
+            || self.audio_mute && self.audio_volume.is_silent();
+
+        self.pins.set_mute(audio_mute);
+    }
+
+    fn set_output(&mut self, audio_output: AudioOutput) {
+        self.dsp_select = Some((audio_output, self.dsp_presets[audio_output as usize]));
+
+        // the relay waits on the speaker protection
+        self.pins.set_speakers(
+            audio_output == AudioOutput::Speakers && self.protection == SpeakerProtection::Ready,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attenuator::VOLUME_UNITY;
+    use crate::menu::MenuPosition;
+    use crate::settings::Settings;
+    use core::convert::Infallible;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// clock the sequence every millisecond from `start`
+    /// for `ms`, returning each step and when it was taken
+    fn run(switch: &mut SwitchSequence, start: TimeInstant, ms: u32) -> Vec<(u32, SwitchAction)> {
+        (0..=ms)
+            .filter_map(|ms| switch.clock(start + ms.millis()).map(|action| (ms, action)))
+            .collect()
+    }
+
+    #[test]
+    fn idle_does_nothing() {
+        let mut switch = SwitchSequence::Idle;
+
+        assert!(switch.is_idle());
+        assert_eq!(run(&mut switch, TimeInstant::from_ticks(0), 1000), []);
+    }
+
+    #[test]
+    fn switches_behind_the_mute() {
+        let start = TimeInstant::from_ticks(0);
+        let mut switch = SwitchSequence::Idle;
+
+        switch.start(start);
+
+        assert!(!switch.is_idle());
+        assert_eq!(
+            run(&mut switch, start, 1000),
+            [
+                (MUTE_SETTLE_MS, SwitchAction::Switch),
+                (MUTE_SETTLE_MS + SWITCH_SETTLE_MS, SwitchAction::Restore),
+            ]
+        );
+        assert!(switch.is_idle());
+    }
+
+    #[test]
+    fn restarting_waits_on_the_mute_again() {
+        let start = TimeInstant::from_ticks(0);
+        let mut switch = SwitchSequence::Idle;
+
+        switch.start(start);
+
+        assert_eq!(
+            run(&mut switch, start, MUTE_SETTLE_MS + 10),
+            [(MUTE_SETTLE_MS, SwitchAction::Switch)]
+        );
+
+        // a second switch while the relays settle
+        let restart = start + (MUTE_SETTLE_MS + 20).millis();
+
+        switch.start(restart);
+
+        assert_eq!(
+            run(&mut switch, restart, 1000),
+            [
+                (MUTE_SETTLE_MS, SwitchAction::Switch),
+                (MUTE_SETTLE_MS + SWITCH_SETTLE_MS, SwitchAction::Restore),
+            ]
+        );
+    }
+
+    #[test]
+    fn late_clocks_still_take_every_step() {
+        let start = TimeInstant::from_ticks(0);
+        let mut switch = SwitchSequence::Idle;
+
+        switch.start(start);
+
+        let late = start + 500.millis();
+
+        assert_eq!(switch.clock(late), Some(SwitchAction::Switch));
+        assert_eq!(switch.clock(late), None);
+        assert_eq!(
+            switch.clock(late + SWITCH_SETTLE_MS.millis()),
+            Some(SwitchAction::Restore)
+        );
+    }
+
+    #[test]
+    fn sequences_across_the_timer_wrapping() {
+        let start = TimeInstant::from_ticks(u32::MAX - 8_000);
+        let mut switch = SwitchSequence::Idle;
+
+        switch.start(start);
+
+        assert_eq!(
+            run(&mut switch, start, 1000),
+            [
+                (MUTE_SETTLE_MS, SwitchAction::Switch),
+                (MUTE_SETTLE_MS + SWITCH_SETTLE_MS, SwitchAction::Restore),
+            ]
+        );
+    }
+
+    /// what control did to the dsp and the pins, in order
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Call {
+        Select(AudioOutput, u8),
+        SetVolume(u8),
+        Speakers(bool),
+        Mute(bool),
+        Input(u8),
+    }
+
+    type Log = Rc<RefCell<Vec<Call>>>;
+
+    struct MockDsp(Log);
+
+    impl Dsp for MockDsp {
+        type Error = Infallible;
+
+        fn select(&mut self, output: AudioOutput, preset: u8) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Call::Select(output, preset));
+
+            Ok(())
+        }
+
+        fn set_volume(&mut self, volume: u8) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Call::SetVolume(volume));
+
+            Ok(())
+        }
+
+        fn status(&mut self) -> Result<DspStatus, Infallible> {
+            Ok(DspStatus { core_control: 0 })
+        }
+    }
+
+    struct MockPins(Log);
+
+    impl ControlPins for MockPins {
+        fn set_speakers(&mut self, speakers: bool) {
+            self.0.borrow_mut().push(Call::Speakers(speakers));
+        }
+
+        fn set_mute(&mut self, mute: bool) {
+            self.0.borrow_mut().push(Call::Mute(mute));
+        }
+
+        fn set_input(&mut self, input: u8) {
+            self.0.borrow_mut().push(Call::Input(input));
+        }
+    }
+
+    /// the attenuator is tested on its own, here it only
+    /// needs to be there
+    struct NoBus;
+
+    impl Write<u8> for NoBus {
+        type Error = Infallible;
+
+        fn write(&mut self, _words: &[u8]) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    impl OutputPin for NoBus {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    /// control along with a dsp that's written as soon as
+    /// control asks, like idle does between clocks
+    struct MockControl {
+        control: Control<MockPins, NoBus, NoBus>,
+        dsp: MockDsp,
+    }
+
+    impl MockControl {
+        fn write(&mut self, state: &State, now: TimeInstant) {
+            self.control.write(state, now);
+            self.write_dsp();
+        }
+
+        fn write_dsp(&mut self) {
+            while let Some(write) = self.control.dsp_write() {
+                let status = write.apply(&mut self.dsp);
+
+                self.control.dsp_written(write, status);
+            }
+        }
+    }
+
+    fn control() -> (MockControl, Log) {
+        let log = Log::default();
+        let control = MockControl {
+            control: Control::new(MockPins(log.clone()), Attenuator::new(NoBus, NoBus)),
+            dsp: MockDsp(log.clone()),
+        };
+
+        (control, log)
+    }
+
+    fn at(ms: u32) -> TimeInstant {
+        TimeInstant::from_ticks(0) + ms.millis()
+    }
+
+    /// clock control every millisecond from `from` up to
+    /// but not including `to`
+    fn clock(control: &mut MockControl, from: u32, to: u32) {
+        for ms in from..to {
+            control.control.clock(at(ms));
+            control.write_dsp();
+        }
+    }
+
+    /// the calls other than the mute, which is set on
+    /// every clock
+    fn switched(log: &Log) -> Vec<Call> {
+        log.take()
+            .into_iter()
+            .filter(|call| !matches!(call, Call::Mute(_)))
+            .collect()
+    }
+
+    fn on_speakers() -> State {
+        let mut settings = Settings::default();
+
+        settings.switch_output(AudioOutput::Speakers);
+
+        State::running(settings, at(0))
+    }
+
+    fn fault() -> State {
+        Fault {
+            settings: Settings::default(),
+            cleared: None,
+        }
+    }
+
+    #[test]
+    fn speakers_wait_for_the_amplifier() {
+        let (mut control, log) = control();
+
+        control.write(&on_speakers(), at(0));
+        clock(&mut control, 0, SPEAKER_DELAY_MS);
+
+        // the dsp is routed straight away, but the relay is
+        // left on the headphones
+        assert_eq!(
+            switched(&log),
+            [
+                Call::SetVolume(VOLUME_UNITY),
+                Call::Speakers(false),
+                Call::Input(0),
+                Call::Select(AudioOutput::Speakers, 0),
+            ]
+        );
+
+        clock(&mut control, SPEAKER_DELAY_MS, SPEAKER_DELAY_MS + 1000);
+
+        assert_eq!(
+            switched(&log),
+            [
+                Call::Speakers(true),
+                Call::Input(0),
+                Call::Select(AudioOutput::Speakers, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn mute_is_held_while_switching() {
+        let (mut control, log) = control();
+
+        control.write(&on_speakers(), at(0));
+        clock(&mut control, 0, SPEAKER_DELAY_MS + 1000);
+        log.take();
+
+        let mut state = on_speakers();
+
+        state.settings_mut().audio_input = 2;
+
+        let start = SPEAKER_DELAY_MS + 1000;
+
+        control.write(&state, at(start));
+        clock(&mut control, start, start + 1000);
+
+        let calls = log.take();
+        let input = calls
+            .iter()
+            .position(|call| *call == Call::Input(2))
+            .unwrap();
+        let released = calls
+            .iter()
+            .position(|call| *call == Call::Mute(false))
+            .unwrap();
+
+        assert!(calls[..input].contains(&Call::Mute(true)));
+        assert!(calls[..released]
+            .iter()
+            .all(|call| *call != Call::Mute(false)));
+        assert!(input < released);
+    }
+
+    #[test]
+    fn clocking_leaves_the_dsp_to_idle() {
+        let (mut control, log) = control();
+
+        control.control.write(&on_speakers(), at(0));
+
+        for ms in 0..1000 {
+            control.control.clock(at(ms));
+        }
+
+        let calls = log.take();
+
+        assert!(!calls
+            .iter()
+            .any(|call| matches!(call, Call::Select(..) | Call::SetVolume(_))));
+
+        // the mute waits on the dsp as well as the relays
+        assert_eq!(calls.last(), Some(&Call::Mute(true)));
+        assert_eq!(
+            control.control.dsp_write(),
+            Some(DspWrite::Select(AudioOutput::Speakers, 0))
+        );
+
+        control.write_dsp();
+        control.control.clock(at(1000));
+
+        assert_eq!(control.control.dsp_write(), None);
+        assert_eq!(
+            control.control.dsp_status(),
+            Some(DspStatus { core_control: 0 })
+        );
+        assert_eq!(
+            log.take(),
+            [
+                Call::Select(AudioOutput::Speakers, 0),
+                Call::SetVolume(VOLUME_UNITY),
+                Call::Mute(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn fault_selects_the_headphones_once() {
+        let (mut control, log) = control();
+
+        control.write(&on_speakers(), at(0));
+        clock(&mut control, 0, SPEAKER_DELAY_MS + 1000);
+        log.take();
+
+        // every message handled in the fault writes the
+        // state out again
+        for ms in 0..50 {
+            control.write(&fault(), at(SPEAKER_DELAY_MS + 1000 + ms));
+        }
+
+        assert_eq!(
+            switched(&log),
+            [
+                Call::Speakers(false),
+                Call::Select(AudioOutput::Headphones, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn fault_holds_the_mute() {
+        let (mut control, log) = control();
+
+        control.write(&on_speakers(), at(0));
+        clock(&mut control, 0, 1000);
+        control.write(&fault(), at(1000));
+        log.take();
+        clock(&mut control, 1000, 10_000);
+
+        assert!(log.take().iter().all(|call| *call == Call::Mute(true)));
+    }
+
+    #[test]
+    fn speakers_are_held_off_again_after_a_fault() {
+        let (mut control, log) = control();
+
+        control.write(&on_speakers(), at(0));
+        clock(&mut control, 0, SPEAKER_DELAY_MS + 1000);
+        control.write(&fault(), at(SPEAKER_DELAY_MS + 1000));
+
+        let recovered = SPEAKER_DELAY_MS + 2000;
+
+        control.write(&on_speakers(), at(recovered));
+        log.take();
+        clock(&mut control, recovered, recovered + 1000);
+
+        assert!(!log.take().contains(&Call::Speakers(true)));
+
+        clock(
+            &mut control,
+            recovered + 1000,
+            recovered + SPEAKER_DELAY_MS + 1000,
+        );
+
+        assert!(log.take().contains(&Call::Speakers(true)));
+    }
+
+    #[test]
+    fn standby_mutes_straight_away() {
+        let (mut control, log) = control();
+
+        control.write(&on_speakers(), at(0));
+        clock(&mut control, 0, 1000);
+        log.take();
+        control.write(
+            &Standby {
+                settings: Settings::default(),
+            },
+            at(1000),
+        );
+
+        assert_eq!(log.take(), [Call::Mute(true)]);
+    }
+
+    #[test]
+    fn dsp_volume_is_only_sent_when_it_changes() {
+        let (mut control, log) = control();
+        let state = State::running(Settings::default(), at(0));
+
+        control.write(&state, at(0));
+        control.write(&state, at(1));
+
+        let menu = Menu {
+            settings: Settings::default(),
+            position: MenuPosition::new(at(2)),
+        };
+
+        control.write(&menu, at(2));
+
+        let volumes = log
+            .take()
+            .into_iter()
+            .filter(|call| matches!(call, Call::SetVolume(_)))
+            .count();
+
+        assert_eq!(volumes, 1);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::attenuator::VOLUME_UNITY;
+use crate::control::AudioOutput;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+/// the number of eq and crossover presets the dsp
+/// program holds for each output
+pub const DSP_PRESETS: u8 = 4;
+
+/// what the dsp reports back about itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DspStatus {
+    pub core_control: u16,
+}
+
+/// the register level protocol of the downstream dsp
+pub trait Dsp {
+    type Error;
+
+    /// route the dsp to `output` using one of its presets
+    fn select(&mut self, output: AudioOutput, preset: u8) -> Result<(), Self::Error>;
+
+    /// set the dsp's own volume, on the same half db
+    /// scale as the attenuator
+    fn set_volume(&mut self, volume: u8) -> Result<(), Self::Error>;
+
+    /// read back the dsp's status registers
+    fn status(&mut self) -> Result<DspStatus, Self::Error>;
+}
+
+/// 7 bit address with both address pins low
+const ADDRESS: u8 = 0x34;
+/// the safeload data registers, each takes five bytes
+const SAFELOAD_DATA: u16 = 0x0810;
+/// the safeload address registers, each takes two bytes
+const SAFELOAD_ADDRESS: u16 = 0x0815;
+const CORE_CONTROL: u16 = 0x081c;
+/// initiate safeload transfer bit of the core control
+const CORE_CONTROL_IST: u16 = 1 << 5;
+
+/// parameter ram addresses of the cells exposed by the
+/// dsp program, these come from the sigmastudio export
+/// and move if the program is rebuilt
+const OUTPUT_MUX: u16 = 0x0000;
+const PRESET_MUX: u16 = 0x0001;
+const VOLUME: u16 = 0x0002;
+
+/// one half db step as a linear gain
+const HALF_DB: f32 = 0.944_061;
+
+/// an adau1701 style sigmadsp running a program with an
+/// output mux, a preset mux and a volume cell.
+///
+/// parameters are written through the safeload registers
+/// so they change between audio frames without clicks.
+pub struct SigmaDsp<I2c> {
+    i2c: I2c,
+}
+
+impl<I2c, E> SigmaDsp<I2c>
+where
+    I2c: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I2c) -> Self {
+        Self { i2c }
+    }
+
+    fn read_register(&mut self, register: u16) -> Result<u16, E> {
+        let mut buffer = [0; 2];
+
+        self.i2c
+            .write_read(ADDRESS, &register.to_be_bytes(), &mut buffer)?;
+
+        Ok(u16::from_be_bytes(buffer))
+    }
+
+    fn write_register(&mut self, register: u16, data: &[u8]) -> Result<(), E> {
+        let mut buffer = [0; 7];
+        let [high, low] = register.to_be_bytes();
+
+        buffer[0] = high;
+        buffer[1] = low;
+        buffer[2..2 + data.len()].copy_from_slice(data);
+
+        self.i2c.write(ADDRESS, &buffer[..2 + data.len()])
+    }
+
+    /// write a parameter through the first safeload slot
+    fn safeload(&mut self, address: u16, value: u32) -> Result<(), E> {
+        let [a, b, c, d] = value.to_be_bytes();
+
+        self.write_register(SAFELOAD_DATA, &[0, a, b, c, d])?;
+        self.write_register(SAFELOAD_ADDRESS, &address.to_be_bytes())?;
+
+        let core_control = self.read_register(CORE_CONTROL)? | CORE_CONTROL_IST;
+
+        self.write_register(CORE_CONTROL, &core_control.to_be_bytes())
+    }
+}
+
+impl<I2c, E> Dsp for SigmaDsp<I2c>
+where
+    I2c: Write<Error = E> + WriteRead<Error = E>,
+{
+    type Error = E;
+
+    fn select(&mut self, output: AudioOutput, preset: u8) -> Result<(), E> {
+        self.safeload(OUTPUT_MUX, output as u32)?;
+        self.safeload(PRESET_MUX, preset.min(DSP_PRESETS - 1) as u32)
+    }
+
+    fn set_volume(&mut self, volume: u8) -> Result<(), E> {
+        let mut gain = 0.0;
+
+        if volume > 0 {
+            gain = 1.0;
+
+            for _ in volume.min(VOLUME_UNITY)..VOLUME_UNITY {
+                gain *= HALF_DB;
+            }
+        }
+
+        // the dsp works in 5.23 fixed point
+        self.safeload(VOLUME, (gain * (1 << 23) as f32) as u32)
+    }
+
+    fn status(&mut self) -> Result<DspStatus, E> {
+        Ok(DspStatus {
+            core_control: self.read_register(CORE_CONTROL)?,
+        })
+    }
+}
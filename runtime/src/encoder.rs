@@ -0,0 +1,148 @@
+/// quarter steps between two detents
+const STEPS_PER_DETENT: i8 = 4;
+/// the pin levels an encoder rests at between detents
+const REST: u8 = 0b11;
+
+/// decodes quadrature pin levels into detent turns.
+///
+/// every transition is counted as a quarter step in
+/// either direction, so contact bounce counts back and
+/// forth and cancels itself out. transitions where both
+/// pins change at once can't be placed and are ignored,
+/// and the count saturates rather than wrapping when
+/// enough of them are missed that it never seems to get
+/// back to rest. a turn is only reported once the encoder is back at
+/// rest a whole detent away, anything less is dropped.
+pub struct QuadratureDecoder {
+    state: u8,
+    count: i8,
+}
+
+impl QuadratureDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: REST,
+            count: 0,
+        }
+    }
+
+    /// feed in the current pin levels, returning the
+    /// direction of a completed detent turn
+    pub fn update(&mut self, a: bool, b: bool) -> Option<i8> {
+        let next = (a as u8) << 1 | b as u8;
+
+        self.count = self.count.saturating_add(match (self.state, next) {
+            (0b11, 0b10) | (0b10, 0b00) | (0b00, 0b01) | (0b01, 0b11) => 1,
+            (0b11, 0b01) | (0b01, 0b00) | (0b00, 0b10) | (0b10, 0b11) => -1,
+            _ => 0,
+        });
+
+        self.state = next;
+
+        if next != REST {
+            return None;
+        }
+
+        let count = core::mem::replace(&mut self.count, 0);
+
+        if count.abs() >= STEPS_PER_DETENT {
+            Some(count.signum())
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for QuadratureDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the pin levels for one detent clockwise, from rest
+    /// back to rest
+    const CLOCKWISE: [(bool, bool); 4] =
+        [(true, false), (false, false), (false, true), (true, true)];
+    const ANTICLOCKWISE: [(bool, bool); 4] =
+        [(false, true), (false, false), (true, false), (true, true)];
+
+    fn run(decoder: &mut QuadratureDecoder, levels: &[(bool, bool)]) -> Vec<i8> {
+        levels
+            .iter()
+            .filter_map(|(a, b)| decoder.update(*a, *b))
+            .collect()
+    }
+
+    #[test]
+    fn whole_detents_turn() {
+        let mut decoder = QuadratureDecoder::new();
+
+        assert_eq!(run(&mut decoder, &CLOCKWISE), [1]);
+        assert_eq!(run(&mut decoder, &ANTICLOCKWISE), [-1]);
+        assert_eq!(run(&mut decoder, &[CLOCKWISE, CLOCKWISE].concat()), [1, 1]);
+    }
+
+    #[test]
+    fn bounce_cancels_out() {
+        let mut decoder = QuadratureDecoder::new();
+        // a clockwise detent with the a contact chattering
+        // as it opens and the b contact as it closes
+        let levels = [
+            (true, false),
+            (true, true),
+            (true, false),
+            (true, true),
+            (true, false),
+            (false, false),
+            (false, true),
+            (false, false),
+            (false, true),
+            (true, true),
+        ];
+
+        assert_eq!(run(&mut decoder, &levels), [1]);
+    }
+
+    #[test]
+    fn part_turns_are_dropped() {
+        let mut decoder = QuadratureDecoder::new();
+        // nudged half a detent then let go back to rest
+        let levels = [(true, false), (false, false), (true, false), (true, true)];
+
+        assert_eq!(run(&mut decoder, &levels), []);
+        assert_eq!(run(&mut decoder, &CLOCKWISE), [1]);
+    }
+
+    #[test]
+    fn skipped_transitions_are_ignored() {
+        let mut decoder = QuadratureDecoder::new();
+        // both pins seen changing at once part way round
+        let levels = [(true, false), (false, true), (true, true)];
+
+        assert_eq!(run(&mut decoder, &levels), []);
+        assert_eq!(run(&mut decoder, &ANTICLOCKWISE), [-1]);
+    }
+
+    #[test]
+    fn chatter_away_from_rest_saturates() {
+        let mut decoder = QuadratureDecoder::new();
+        let mut levels = vec![(true, false)];
+
+        // spun faster than it's sampled, so every third
+        // transition is missed and it never seems to come
+        // back to rest, far past what the count can hold
+        for _ in 0..100 {
+            levels.extend([(false, false), (false, true), (true, false)]);
+        }
+
+        levels.extend([(false, false), (false, true)]);
+        levels.push((true, true));
+
+        assert_eq!(run(&mut decoder, &levels), [1]);
+        assert_eq!(run(&mut decoder, &ANTICLOCKWISE), [-1]);
+    }
+}
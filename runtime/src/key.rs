@@ -0,0 +1,60 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Key {
+    Unassigned(usize),
+    ToggleBrightness,
+    BrightnessUp,
+    BrightnessDown,
+    TogglePeaks,
+    ToggleLevels,
+    ToggleOutput,
+    ToggleMute,
+    CycleInput,
+    /// every key at once, scanned in standby so any
+    /// press wakes the unit
+    Wake,
+}
+
+impl Key {
+    /// whether holding the key down repeats it
+    pub fn repeats(&self) -> bool {
+        matches!(self, Key::BrightnessUp | Key::BrightnessDown)
+    }
+
+    /// whether holding the key down does something other
+    /// than pressing it, which holds back the press until
+    /// the key is let go
+    pub fn holds(&self) -> bool {
+        matches!(self, Key::ToggleOutput | Key::TogglePeaks | Key::ToggleMute)
+    }
+}
+
+/// which key each row of the keypad is, so the keys
+/// still read the right way round with the unit
+/// mounted upside down
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Keymap {
+    Standard,
+    Flipped,
+}
+
+impl Keymap {
+    /// the key on each row, from the top row down
+    pub fn keys(self) -> [Key; 8] {
+        let mut keys = [
+            Key::ToggleMute,
+            Key::ToggleOutput,
+            Key::BrightnessUp,
+            Key::BrightnessDown,
+            Key::ToggleBrightness,
+            Key::TogglePeaks,
+            Key::ToggleLevels,
+            Key::CycleInput,
+        ];
+
+        if self == Keymap::Flipped {
+            keys.reverse();
+        }
+
+        keys
+    }
+}
@@ -0,0 +1,1139 @@
+// the unit's behaviour, apart from any chip or board.
+//
+// the state machine, its messages and the settings live
+// here along with the drivers that only need the
+// embedded-hal traits, so the firmware for each chip
+// is left with pins, peripherals and tasks, and all of
+// this can be tested on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+use crate::attenuator::{volume_to_db, VOLUME_UNITY};
+use crate::auto_dim::ACTIVITY_LEVEL;
+use crate::control::{AudioOutput, AUDIO_INPUTS};
+use crate::dsp::DSP_PRESETS;
+use crate::key::Key;
+use crate::log::{debug, info, warn, Event};
+use crate::menu::{MenuKey, MenuPosition};
+use crate::meter::{bar, dot, MeterChannel, SegmentMask};
+use crate::settings::{BootSequence, BrightnessLevel, Settings};
+use fugit::ExtU32;
+
+pub mod attenuator;
+pub mod auto_brightness;
+pub mod auto_dim;
+pub mod command;
+pub mod control;
+pub mod dsp;
+pub mod encoder;
+pub mod key;
+pub mod log;
+pub mod menu;
+pub mod meter;
+pub mod modulation;
+pub mod queue;
+pub mod scale;
+pub mod settings;
+
+// frames logged from the tests go nowhere
+#[cfg(test)]
+#[defmt::global_logger]
+struct TestLogger;
+
+#[cfg(test)]
+unsafe impl defmt::Logger for TestLogger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+#[cfg(test)]
+defmt::timestamp!("{=u32}", 0);
+
+#[cfg(not(any(feature = "meter-2x12", feature = "meter-6x12")))]
+compile_error!("pick the meter's size with one of the `meter-*` features");
+
+#[cfg(all(feature = "meter-2x12", feature = "meter-6x12"))]
+compile_error!("pick only one of the `meter-*` features");
+
+/// the segments on each of the meter's channels
+pub const METER_SEGMENTS: usize = 12;
+/// the channels the meter shows
+#[cfg(feature = "meter-2x12")]
+pub const METER_CHANNELS: usize = 2;
+#[cfg(feature = "meter-6x12")]
+pub const METER_CHANNELS: usize = 6;
+
+/// the rate the firmware's monotonic timer counts at,
+/// which every instant here is measured in
+pub const TIMER_HZ: u32 = 8_000_000;
+
+pub type TimeInstant = fugit::TimerInstantU32<TIMER_HZ>;
+pub type TimeDuration = fugit::TimerDurationU32<TIMER_HZ>;
+
+pub use Message::*;
+pub use State::*;
+
+pub const DB_PLUS_12: f32 = 0.9800;
+// pub const DB_PLUS_9: f32 = 0.9500;
+pub const DB_PLUS_6: f32 = 0.9200;
+pub const DB_PLUS_3: f32 = 0.8900;
+pub const DB_NOMINAL: f32 = 0.8600;
+pub const DB_MINUS_3: f32 = 0.8300;
+pub const DB_MINUS_6: f32 = 0.8000;
+// pub const DB_MINUS_9: f32 = 0.7700;
+pub const DB_MINUS_12: f32 = 0.7400;
+// pub const DB_MINUS_15: f32 = 0.7100;
+pub const DB_MINUS_18: f32 = 0.6800;
+// pub const DB_MINUS_21: f32 = 0.6500;
+// pub const DB_MINUS_24: f32 = 0.6200;
+pub const DB_MINUS_27: f32 = 0.5900;
+// pub const DB_MINUS_30: f32 = 0.5600;
+// pub const DB_MINUS_33: f32 = 0.5300;
+pub const DB_MINUS_36: f32 = 0.5000;
+// pub const DB_MINUS_39: f32 = 0.4700;
+// pub const DB_MINUS_42: f32 = 0.4400;
+pub const DB_MINUS_45: f32 = 0.4100;
+// pub const DB_MINUS_48: f32 = 0.3800;
+// pub const DB_MINUS_51: f32 = 0.3500;
+pub const DB_MINUS_54: f32 = 0.3200;
+// pub const DB_MINUS_57: f32 = 0.2900;
+// pub const DB_MINUS_60: f32 = 0.2600;
+// pub const DB_MINUS_63: f32 = 0.2300;
+// pub const DB_MINUS_66: f32 = 0.2000;
+pub const DB_MINUS_INF: f32 = 0.0;
+
+/// the meter input for a level in db, each db being a
+/// hundredth of full scale from the nominal level
+pub fn db_to_input(db: f32) -> f32 {
+    DB_NOMINAL + db / 100.0
+}
+
+/// how far the brightness keys move the brightness
+pub const BRIGHTNESS_STEP: u8 = 2;
+/// how far a detent of the encoder moves the volume,
+/// in half db steps
+pub const VOLUME_STEP: u8 = 2;
+/// how long the fault input has to stay clear before
+/// recovering from a fault
+pub const FAULT_RECOVERY_MS: u32 = 5000;
+/// how often a tick is sent
+pub const TICK_MS: u32 = 100;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Message {
+    Booted,
+    /// sent every `TICK_MS`, so timeouts run out whether
+    /// or not anything else is going on
+    Tick,
+    KeypadUpdate(Key),
+    KeypadHold(Key),
+    /// the input level on each of the meter's channels
+    MeterUpdate([f32; METER_CHANNELS]),
+    SetBrightness(u8),
+    AmbientUpdate(f32),
+    SetAutoBrightness(bool),
+    SetAutoBrightnessPoint(usize, f32, u8),
+    SetAutoDim(u8, u8),
+    SetVolume(u8),
+    SetMuteRamp(u16),
+    EncoderTurn(i8),
+    EncoderPush,
+    HeadphonesInserted(bool),
+    SetHeadphoneDetect(bool),
+    SetInput(u8),
+    FaultUpdate(bool),
+    SetDspPreset(u8),
+    SetDspVolume(u8),
+    /// the segments the self test found dead
+    SelfTestResult(SegmentMask),
+    SelfTestDone,
+    SetBootSequence(BootSequence),
+    /// run the full led test from running
+    RunSelfTest,
+    SetMute(bool),
+    SetPeaks(bool),
+    SetLevels(bool),
+}
+
+/// the time between two instants, which keeps working
+/// across the monotonic timer wrapping as long as the
+/// gap is shorter than the timer's range
+pub fn elapsed(since: TimeInstant, now: TimeInstant) -> TimeDuration {
+    TimeDuration::from_ticks(now.ticks().wrapping_sub(since.ticks()))
+}
+
+impl Message {
+    pub fn send(self) {
+        queue::push(self);
+
+        // wake idle if it's waiting for a message
+        #[cfg(all(target_arch = "arm", target_os = "none"))]
+        cortex_m::asm::sev();
+    }
+}
+
+/// what turning the encoder adjusts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderTarget {
+    Volume,
+    Brightness,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum State {
+    Booting {
+        settings: Settings,
+    },
+    /// running through the boot sequence on the leds
+    SelfTest {
+        settings: Settings,
+        sequence: BootSequence,
+        dead_segments: Option<SegmentMask>,
+    },
+    Running {
+        settings: Settings,
+        channels: [MeterChannel; METER_CHANNELS],
+    },
+    /// the amplifier reported a fault, outputs are held
+    /// muted on headphones until the fault has cleared
+    /// for `FAULT_RECOVERY_MS`
+    Fault {
+        settings: Settings,
+        cleared: Option<TimeInstant>,
+    },
+    /// changing settings on the leds, metering stops
+    /// until the menu is left
+    Menu {
+        settings: Settings,
+        position: MenuPosition,
+    },
+    /// the leds and outputs are off and the core is
+    /// stopped until a key is pressed
+    Standby {
+        settings: Settings,
+    },
+}
+
+impl State {
+    /// start running with `settings`
+    pub fn running(mut settings: Settings, now: TimeInstant) -> State {
+        settings.auto_dim.activity(now);
+
+        Running {
+            settings,
+            channels: [MeterChannel::new(now); METER_CHANNELS],
+        }
+    }
+
+    /// the settings carried by the state
+    pub fn settings(&self) -> Settings {
+        match *self {
+            Booting { settings }
+            | SelfTest { settings, .. }
+            | Running { settings, .. }
+            | Fault { settings, .. }
+            | Menu { settings, .. }
+            | Standby { settings } => settings,
+        }
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        match self {
+            Booting { settings }
+            | SelfTest { settings, .. }
+            | Running { settings, .. }
+            | Fault { settings, .. }
+            | Menu { settings, .. }
+            | Standby { settings } => settings,
+        }
+    }
+
+    /// switch to `audio_output` along with its preset,
+    /// carrying on metering where it left off
+    fn switch_output(&mut self, audio_output: AudioOutput, now: TimeInstant) {
+        let running = matches!(self, Running { .. });
+        let settings = self.settings_mut();
+
+        settings.switch_output(audio_output);
+
+        if running {
+            settings.auto_dim.activity(now);
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Booting { .. } => "booting",
+            SelfTest { .. } => "self-test",
+            Running { .. } => "running",
+            Fault { .. } => "fault",
+            Menu { .. } => "menu",
+            Standby { .. } => "standby",
+        }
+    }
+
+    #[must_use]
+    pub fn recv(mut self, msg: Message, now: TimeInstant) -> State {
+        match msg {
+            KeypadUpdate(key) => log::event(Event::KeyPressed(key)),
+            KeypadHold(key) => log::event(Event::KeyHeld(key)),
+            _ => {}
+        }
+
+        // any key press or turn wakes the leds from auto dim
+        if let (
+            Running { settings, .. },
+            KeypadUpdate(_) | KeypadHold(_) | EncoderTurn(_) | EncoderPush,
+        ) = (&mut self, msg)
+        {
+            if settings.auto_dim.activity(now) {
+                info!("woke from auto dim");
+            }
+        }
+
+        // switching output swaps in the new output's
+        // preset, which is done once the match is over
+        let mut switch_output = None;
+
+        match (&mut self, msg) {
+            (Booting { settings }, Booted) => {
+                if settings.boot_sequence == BootSequence::Off {
+                    return State::running(*settings, now);
+                }
+
+                return SelfTest {
+                    settings: *settings,
+                    sequence: settings.boot_sequence,
+                    dead_segments: None,
+                };
+            }
+
+            (SelfTest { dead_segments, .. }, SelfTestResult(dead)) => {
+                *dead_segments = Some(dead);
+
+                if dead.iter().all(|dead| *dead == 0) {
+                    info!("self test passed");
+                } else {
+                    warn!("self test found dead segments {=[?]:b}", dead);
+                }
+            }
+
+            (SelfTest { settings, .. }, SelfTestDone) => return State::running(*settings, now),
+
+            // force the outputs safe on a fault
+            (Fault { cleared, .. }, FaultUpdate(true)) => {
+                *cleared = None;
+            }
+
+            (_, FaultUpdate(true)) => {
+                let mut settings = self.settings();
+
+                settings.switch_output(AudioOutput::Headphones);
+                settings.audio_mute = true;
+
+                log::event(Event::Fault { active: true });
+                warn!("amplifier fault, muted and switched to headphone output");
+
+                return Fault {
+                    settings,
+                    cleared: None,
+                };
+            }
+
+            // start recovering once the fault clears
+            (Fault { cleared, .. }, FaultUpdate(false)) => {
+                *cleared = Some(now);
+
+                log::event(Event::Fault { active: false });
+            }
+
+            // and recover once it's stayed clear, staying
+            // muted until the user unmutes
+            (
+                Fault {
+                    settings,
+                    cleared: Some(cleared),
+                },
+                Tick,
+            ) if elapsed(*cleared, now).to_millis() >= FAULT_RECOVERY_MS => {
+                info!("recovered from amplifier fault");
+
+                return State::running(*settings, now);
+            }
+
+            // calculate meter peak and level
+            (Running { settings, channels }, MeterUpdate(levels)) => {
+                let scale = settings.scale();
+                let auto_dim = &mut settings.auto_dim;
+
+                if levels.iter().any(|level| *level >= ACTIVITY_LEVEL) {
+                    if auto_dim.activity(now) {
+                        info!("woke from auto dim");
+                    }
+                } else if auto_dim.update(now) {
+                    info!("auto dimmed to {}%", auto_dim.floor);
+                }
+
+                let calculate = |channel: &mut MeterChannel, channel_raw: f32| {
+                    let (lit, step) = scale.find(channel_raw);
+                    let new_level = bar(lit);
+                    let new_peak = dot(lit);
+
+                    if new_peak >= channel.peak || channel.peak_decay < now {
+                        channel.peak = new_peak;
+                        channel.peak_decay = now + step.peak_decay_ms.millis();
+                    }
+
+                    if new_level >= channel.level || channel.level_decay < now {
+                        channel.level = new_level;
+                        channel.partial = scale.partial(lit, channel_raw);
+                        channel.level_decay = now + step.level_decay_ms.millis();
+                    }
+                };
+
+                for (channel, channel_raw) in channels.iter_mut().zip(levels) {
+                    calculate(channel, channel_raw);
+                }
+            }
+
+            // going a while without activity goes to standby
+            (Running { settings, .. }, Tick) => {
+                if settings.auto_dim.update(now) {
+                    info!("auto dimmed to {}%", settings.auto_dim.floor);
+                }
+
+                let minutes = settings.auto_standby_minutes;
+
+                if minutes > 0 && settings.auto_dim.idle_ms() >= minutes as u32 * 60_000 {
+                    info!("went to standby after {} minutes idle", minutes);
+
+                    return Standby {
+                        settings: *settings,
+                    };
+                }
+            }
+
+            // holding the mute key goes to standby
+            (Running { .. }, KeypadHold(Key::ToggleMute)) => {
+                info!("went to standby");
+
+                return Standby {
+                    settings: self.settings(),
+                };
+            }
+
+            // and any key wakes it back up
+            (Standby { settings }, KeypadUpdate(_) | KeypadHold(_)) => {
+                info!("woke from standby");
+
+                return State::running(*settings, now);
+            }
+
+            // holding the peaks key opens the menu
+            (Running { .. }, KeypadHold(Key::TogglePeaks)) => {
+                info!("opened menu");
+
+                return Menu {
+                    settings: self.settings(),
+                    position: MenuPosition::new(now),
+                };
+            }
+
+            // move around the menu with the keys or the
+            // encoder, backing out of it returns to metering
+            (Menu { settings, position }, KeypadUpdate(_) | EncoderTurn(_) | EncoderPush) => {
+                let menu_key = match msg {
+                    KeypadUpdate(key) => MenuKey::from_key(key),
+                    EncoderTurn(direction) if direction > 0 => Some(MenuKey::Up),
+                    EncoderTurn(_) => Some(MenuKey::Down),
+                    EncoderPush => Some(MenuKey::Select),
+                    _ => None,
+                };
+
+                if let Some(menu_key) = menu_key {
+                    if !position.key(menu_key, settings, now) {
+                        info!("closed menu");
+
+                        return State::running(*settings, now);
+                    }
+
+                    let item = position.item();
+
+                    debug!(
+                        "menu {} {}{}",
+                        item.name(),
+                        item.level(settings),
+                        if position.editing { " editing" } else { "" }
+                    );
+                }
+            }
+
+            // and so does leaving it alone
+            (Menu { settings, position }, Tick) if position.timed_out(now) => {
+                info!("menu timed out");
+
+                return State::running(*settings, now);
+            }
+
+            // run the full led test on request
+            (Running { .. }, RunSelfTest) => {
+                return SelfTest {
+                    settings: self.settings(),
+                    sequence: BootSequence::Full,
+                    dead_segments: None,
+                };
+            }
+
+            // toggle meter peaks
+            (Running { settings, .. }, KeypadUpdate(Key::TogglePeaks)) => {
+                settings.peaks = !settings.peaks;
+
+                info!(
+                    "turned {} peaks display",
+                    if settings.peaks { "on" } else { "off" }
+                );
+            }
+
+            (Running { settings, .. }, SetPeaks(on)) => {
+                settings.peaks = on;
+
+                info!(
+                    "turned {} peaks display",
+                    if settings.peaks { "on" } else { "off" }
+                );
+            }
+
+            (Running { settings, .. }, SetLevels(on)) => {
+                settings.levels = on;
+
+                info!(
+                    "turned {} levels display",
+                    if settings.levels { "on" } else { "off" }
+                );
+            }
+
+            // toggle meter levels
+            (Running { settings, .. }, KeypadUpdate(Key::ToggleLevels)) => {
+                settings.levels = !settings.levels;
+
+                info!(
+                    "turned {} levels display",
+                    if settings.levels { "on" } else { "off" }
+                );
+            }
+
+            // toggle output between headphones and speakers
+            (Running { settings, .. }, KeypadUpdate(Key::ToggleOutput)) => {
+                switch_output = Some(match settings.audio_output {
+                    AudioOutput::Headphones => {
+                        info!("switched to speaker output");
+
+                        AudioOutput::Speakers
+                    }
+                    AudioOutput::Speakers => {
+                        info!("switched to headphone output");
+
+                        AudioOutput::Headphones
+                    }
+                });
+            }
+
+            // holding the output key cycles the dsp preset
+            // for the current output
+            (Running { settings, .. }, KeypadHold(Key::ToggleOutput)) => {
+                let preset = &mut settings.dsp_presets[settings.audio_output as usize];
+
+                *preset = (*preset + 1) % DSP_PRESETS;
+
+                info!("switched to dsp preset {}", *preset + 1);
+            }
+
+            // select a dsp preset from the control interface
+            (Running { settings, .. }, SetDspPreset(preset)) if preset < DSP_PRESETS => {
+                settings.dsp_presets[settings.audio_output as usize] = preset;
+
+                info!("switched to dsp preset {}", preset + 1);
+            }
+
+            // set the dsp volume
+            (Running { settings, .. }, SetDspVolume(volume)) => {
+                settings.dsp_volume = volume.min(VOLUME_UNITY);
+
+                match volume_to_db(settings.dsp_volume) {
+                    Some(db) => info!("set dsp volume to {}db", db),
+                    None => info!("set dsp volume to fully attenuated"),
+                }
+            }
+
+            // follow headphones being plugged in, whatever
+            // the state, so the level the jack settles on
+            // while booting or in the menu isn't lost. a
+            // fault holds the outputs on headphones itself
+            // and picks this up once it has recovered
+            (state, HeadphonesInserted(true)) if state.settings().headphone_detect => {
+                switch_output = Some(AudioOutput::Headphones);
+
+                info!("headphones plugged in, switched to headphone output");
+            }
+
+            // and back to speakers when they're unplugged
+            (state, HeadphonesInserted(false)) if state.settings().headphone_detect => {
+                switch_output = Some(AudioOutput::Speakers);
+
+                info!("headphones unplugged, switched to speaker output");
+            }
+
+            // turn headphone detection on or off
+            (Running { settings, .. }, SetHeadphoneDetect(enabled)) => {
+                settings.headphone_detect = enabled;
+
+                info!(
+                    "turned {} headphone detection",
+                    if settings.headphone_detect {
+                        "on"
+                    } else {
+                        "off"
+                    }
+                );
+            }
+
+            // cycle through the inputs
+            (Running { settings, .. }, KeypadUpdate(Key::CycleInput)) => {
+                settings.audio_input = (settings.audio_input + 1) % AUDIO_INPUTS;
+
+                info!("switched to input {}", settings.audio_input + 1);
+            }
+
+            // select an input from the control interface
+            (Running { settings, .. }, SetInput(input)) if input < AUDIO_INPUTS => {
+                settings.audio_input = input;
+
+                info!("switched to input {}", settings.audio_input + 1);
+            }
+
+            // toggle output mute
+            (Running { settings, .. }, KeypadUpdate(Key::ToggleMute)) => {
+                settings.audio_mute = !settings.audio_mute;
+
+                info!(
+                    "{} audio output",
+                    if settings.audio_mute {
+                        "muted"
+                    } else {
+                        "unmuted"
+                    }
+                );
+            }
+
+            (Running { settings, .. }, SetMute(mute)) => {
+                settings.audio_mute = mute;
+
+                info!(
+                    "{} audio output",
+                    if settings.audio_mute {
+                        "muted"
+                    } else {
+                        "unmuted"
+                    }
+                );
+            }
+
+            // set output volume
+            (Running { settings, .. }, SetVolume(volume)) => {
+                settings.audio_volume = volume.min(VOLUME_UNITY);
+
+                match volume_to_db(settings.audio_volume) {
+                    Some(db) => info!("set volume to {}db", db),
+                    None => info!("set volume to fully attenuated"),
+                }
+            }
+
+            // switch what the encoder adjusts
+            (Running { settings, .. }, EncoderPush) => {
+                settings.encoder = match settings.encoder {
+                    EncoderTarget::Volume => {
+                        info!("encoder adjusts brightness");
+
+                        EncoderTarget::Brightness
+                    }
+                    EncoderTarget::Brightness => {
+                        info!("encoder adjusts volume");
+
+                        EncoderTarget::Volume
+                    }
+                }
+            }
+
+            // turn the volume up or down
+            (Running { settings, .. }, EncoderTurn(direction))
+                if settings.encoder == EncoderTarget::Volume =>
+            {
+                settings.audio_volume = if direction > 0 {
+                    settings
+                        .audio_volume
+                        .saturating_add(VOLUME_STEP)
+                        .min(VOLUME_UNITY)
+                } else {
+                    settings.audio_volume.saturating_sub(VOLUME_STEP)
+                };
+
+                match volume_to_db(settings.audio_volume) {
+                    Some(db) => info!("set volume to {}db", db),
+                    None => info!("set volume to fully attenuated"),
+                }
+            }
+
+            // turn the brightness up or down
+            (Running { settings, .. }, EncoderTurn(direction)) => {
+                settings.auto_brightness = false;
+                settings.brightness = if direction > 0 {
+                    settings.brightness.saturating_add(BRIGHTNESS_STEP).min(100)
+                } else {
+                    settings.brightness.saturating_sub(BRIGHTNESS_STEP)
+                };
+
+                info!("set brightness to {}%", settings.brightness);
+            }
+
+            // set how long a soft mute takes
+            (Running { settings, .. }, SetMuteRamp(ramp_ms)) => {
+                settings.mute_ramp_ms = ramp_ms;
+
+                info!("set mute ramp to {}ms", ramp_ms);
+            }
+
+            // cycle through the brightness presets, then auto
+            (Running { settings, .. }, KeypadUpdate(Key::ToggleBrightness)) => {
+                if settings.auto_brightness {
+                    settings.auto_brightness = false;
+                    settings.brightness = BrightnessLevel::High.percent();
+
+                    info!("switched to high brightness");
+                } else if settings.brightness <= BrightnessLevel::Low.percent() {
+                    settings.auto_brightness = true;
+                    settings.brightness_curve.reset();
+
+                    info!("switched to auto brightness");
+                } else {
+                    let level = BrightnessLevel::below(settings.brightness);
+
+                    settings.brightness = level.percent();
+
+                    info!(
+                        "switched to {} brightness",
+                        match level {
+                            BrightnessLevel::High => "high",
+                            BrightnessLevel::Medium => "medium",
+                            BrightnessLevel::Low => "low",
+                        }
+                    );
+                }
+            }
+
+            // step brightness up
+            (Running { settings, .. }, KeypadUpdate(Key::BrightnessUp)) => {
+                settings.auto_brightness = false;
+                settings.brightness = settings.brightness.saturating_add(BRIGHTNESS_STEP).min(100);
+
+                info!("set brightness to {}%", settings.brightness);
+            }
+
+            // step brightness down
+            (Running { settings, .. }, KeypadUpdate(Key::BrightnessDown)) => {
+                settings.auto_brightness = false;
+                settings.brightness = settings.brightness.saturating_sub(BRIGHTNESS_STEP);
+
+                info!("set brightness to {}%", settings.brightness);
+            }
+
+            // set brightness from the control interface
+            (Running { settings, .. }, SetBrightness(percent)) => {
+                settings.auto_brightness = false;
+                settings.brightness = percent.min(100);
+
+                info!("set brightness to {}%", settings.brightness);
+            }
+
+            // follow the ambient light in auto brightness
+            (Running { settings, .. }, AmbientUpdate(lux)) if settings.auto_brightness => {
+                settings.brightness_curve.update(lux);
+            }
+
+            // turn auto brightness on or off
+            (Running { settings, .. }, SetAutoBrightness(enabled)) => {
+                settings.auto_brightness = enabled;
+                settings.brightness_curve.reset();
+
+                info!(
+                    "turned {} auto brightness",
+                    if settings.auto_brightness {
+                        "on"
+                    } else {
+                        "off"
+                    }
+                );
+            }
+
+            // move a point on the auto brightness curve
+            (Running { settings, .. }, SetAutoBrightnessPoint(index, lux, percent)) => {
+                settings.brightness_curve.set_point(index, lux, percent);
+
+                info!(
+                    "set auto brightness point {} to {} lux at {}%",
+                    index, lux, percent
+                );
+            }
+
+            // set the auto dim timeout and floor
+            (Running { settings, .. }, SetAutoDim(timeout_minutes, floor)) => {
+                settings.auto_dim.timeout_minutes = timeout_minutes;
+                settings.auto_dim.activity(now);
+
+                if timeout_minutes > 0 {
+                    settings.auto_dim.floor = floor.min(100);
+
+                    info!(
+                        "set auto dim to {}% after {} minutes",
+                        settings.auto_dim.floor, timeout_minutes
+                    );
+                } else {
+                    info!("turned off auto dim");
+                }
+            }
+
+            // choose what's shown while booting
+            (Running { settings, .. }, SetBootSequence(sequence)) => {
+                settings.boot_sequence = sequence;
+
+                info!("set boot sequence to {:?}", sequence);
+            }
+
+            (Running { .. }, KeypadUpdate(Key::Unassigned(num))) => {
+                debug!("unassigned key {} pressed", num);
+            }
+
+            _ => {}
+        };
+
+        if let Some(audio_output) = switch_output {
+            self.switch_output(audio_output, now);
+        }
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::menu::MENU_TIMEOUT_MS;
+
+    fn at(ms: u32) -> TimeInstant {
+        TimeInstant::from_ticks(0) + ms.millis()
+    }
+
+    /// running on speakers, unmuted
+    fn running() -> State {
+        let mut settings = Settings::default();
+
+        settings.switch_output(AudioOutput::Speakers);
+
+        State::running(settings, at(0))
+    }
+
+    /// feed `state` a message at each time in turn
+    fn run(mut state: State, msgs: &[(u32, Message)]) -> State {
+        for (ms, msg) in msgs {
+            state = state.recv(*msg, at(*ms));
+        }
+
+        state
+    }
+
+    #[test]
+    fn fault_mutes_onto_headphones() {
+        let state = run(running(), &[(10, FaultUpdate(true))]);
+
+        assert!(matches!(state, Fault { cleared: None, .. }));
+        assert!(state.settings().audio_mute);
+        assert_eq!(state.settings().audio_output, AudioOutput::Headphones);
+    }
+
+    #[test]
+    fn fault_is_taken_from_any_state() {
+        let settings = Settings::default();
+        let states = [
+            Booting { settings },
+            SelfTest {
+                settings,
+                sequence: BootSequence::Full,
+                dead_segments: None,
+            },
+            Menu {
+                settings,
+                position: MenuPosition::new(at(0)),
+            },
+            Standby { settings },
+        ];
+
+        for state in states {
+            assert!(matches!(state.recv(FaultUpdate(true), at(0)), Fault { .. }));
+        }
+    }
+
+    #[test]
+    fn recovers_once_clear_for_long_enough() {
+        let state = run(
+            running(),
+            &[(0, FaultUpdate(true)), (100, FaultUpdate(false))],
+        );
+
+        assert!(matches!(
+            state,
+            Fault {
+                cleared: Some(_),
+                ..
+            }
+        ));
+
+        let state = run(state, &[(100 + FAULT_RECOVERY_MS - 1, Tick)]);
+
+        assert!(matches!(state, Fault { .. }));
+
+        let state = run(state, &[(100 + FAULT_RECOVERY_MS, Tick)]);
+
+        assert!(matches!(state, Running { .. }));
+    }
+
+    #[test]
+    fn recovery_needs_no_meter_input() {
+        // ticks alone, as when nothing is playing or the
+        // meter's converter has stopped
+        let mut msgs = vec![(0, FaultUpdate(true)), (0, FaultUpdate(false))];
+
+        msgs.extend((1..=FAULT_RECOVERY_MS / TICK_MS).map(|tick| (tick * TICK_MS, Tick)));
+
+        assert!(matches!(run(running(), &msgs), Running { .. }));
+    }
+
+    #[test]
+    fn meter_updates_do_not_recover() {
+        let state = run(
+            running(),
+            &[
+                (0, FaultUpdate(true)),
+                (0, FaultUpdate(false)),
+                (FAULT_RECOVERY_MS * 2, MeterUpdate([0.0; METER_CHANNELS])),
+            ],
+        );
+
+        assert!(matches!(state, Fault { .. }));
+    }
+
+    #[test]
+    fn fault_returning_restarts_recovery() {
+        let state = run(
+            running(),
+            &[
+                (0, FaultUpdate(true)),
+                (0, FaultUpdate(false)),
+                (FAULT_RECOVERY_MS - 100, FaultUpdate(true)),
+                (FAULT_RECOVERY_MS, Tick),
+            ],
+        );
+
+        assert!(matches!(state, Fault { cleared: None, .. }));
+
+        let state = run(
+            state,
+            &[
+                (FAULT_RECOVERY_MS + 100, FaultUpdate(false)),
+                (FAULT_RECOVERY_MS * 2, Tick),
+            ],
+        );
+
+        assert!(matches!(state, Fault { .. }));
+
+        let state = run(state, &[(FAULT_RECOVERY_MS * 2 + 100, Tick)]);
+
+        assert!(matches!(state, Running { .. }));
+    }
+
+    #[test]
+    fn recovers_muted_on_headphones() {
+        let state = run(
+            running(),
+            &[
+                (0, FaultUpdate(true)),
+                (0, FaultUpdate(false)),
+                (FAULT_RECOVERY_MS, Tick),
+            ],
+        );
+        let settings = state.settings();
+
+        assert!(matches!(state, Running { .. }));
+        assert!(settings.audio_mute);
+        assert_eq!(settings.audio_output, AudioOutput::Headphones);
+    }
+
+    #[test]
+    fn jack_is_followed_in_every_state() {
+        let settings = Settings::default();
+        let states = [
+            Booting { settings },
+            SelfTest {
+                settings,
+                sequence: BootSequence::Full,
+                dead_segments: None,
+            },
+            running(),
+            Menu {
+                settings,
+                position: MenuPosition::new(at(0)),
+            },
+            Standby { settings },
+        ];
+
+        for state in states {
+            let name = state.name();
+            let state = run(state, &[(0, HeadphonesInserted(false))]);
+
+            assert_eq!(
+                state.settings().audio_output,
+                AudioOutput::Speakers,
+                "{}",
+                name
+            );
+
+            let state = run(state, &[(10, HeadphonesInserted(true))]);
+
+            assert_eq!(
+                state.settings().audio_output,
+                AudioOutput::Headphones,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn jack_seen_while_booting_carries_into_running() {
+        let state = run(
+            Booting {
+                settings: Settings::default(),
+            },
+            &[
+                (0, Booted),
+                (10, HeadphonesInserted(false)),
+                (20, SelfTestDone),
+            ],
+        );
+
+        assert!(matches!(state, Running { .. }));
+        assert_eq!(state.settings().audio_output, AudioOutput::Speakers);
+    }
+
+    #[test]
+    fn jack_is_ignored_with_detection_off() {
+        let mut settings = Settings::default();
+
+        settings.headphone_detect = false;
+
+        let state = run(
+            State::running(settings, at(0)),
+            &[(0, HeadphonesInserted(false))],
+        );
+
+        assert_eq!(state.settings().audio_output, AudioOutput::Headphones);
+    }
+
+    #[test]
+    fn jack_pulled_in_a_fault_is_kept_for_recovery() {
+        let state = run(
+            running(),
+            &[
+                (0, FaultUpdate(true)),
+                (10, HeadphonesInserted(true)),
+                (20, HeadphonesInserted(false)),
+                (30, FaultUpdate(false)),
+                (30 + FAULT_RECOVERY_MS, Tick),
+            ],
+        );
+
+        assert!(matches!(state, Running { .. }));
+        assert_eq!(state.settings().audio_output, AudioOutput::Speakers);
+        assert!(state.settings().audio_mute);
+    }
+
+    #[test]
+    fn keys_are_ignored_while_faulted() {
+        let state = run(
+            running(),
+            &[
+                (0, FaultUpdate(true)),
+                (10, KeypadUpdate(Key::ToggleMute)),
+                (20, KeypadUpdate(Key::ToggleOutput)),
+            ],
+        );
+
+        assert!(state.settings().audio_mute);
+        assert_eq!(state.settings().audio_output, AudioOutput::Headphones);
+    }
+
+    #[test]
+    fn menu_times_out_on_ticks() {
+        let state = run(running(), &[(0, KeypadHold(Key::TogglePeaks))]);
+
+        assert!(matches!(state, Menu { .. }));
+
+        let state = run(state, &[(MENU_TIMEOUT_MS - TICK_MS, Tick)]);
+
+        assert!(matches!(state, Menu { .. }));
+
+        let state = run(state, &[(MENU_TIMEOUT_MS, Tick)]);
+
+        assert!(matches!(state, Running { .. }));
+    }
+
+    #[test]
+    fn goes_to_standby_when_idle() {
+        let mut state = running();
+        let mut now = at(0);
+
+        state.settings_mut().auto_standby_minutes = 15;
+
+        // the timer wraps long before that, so step there
+        for _ in 0..15 * 2 - 1 {
+            now += 30.secs();
+            state = state.recv(Tick, now);
+        }
+
+        assert!(matches!(state, Running { .. }));
+
+        now += 30.secs();
+        state = state.recv(Tick, now);
+
+        assert!(matches!(state, Standby { .. }));
+    }
+
+    #[test]
+    fn keys_hold_off_standby() {
+        let mut state = running();
+        let mut now = at(0);
+
+        state.settings_mut().auto_standby_minutes = 15;
+
+        for step in 0..15 * 2 {
+            now += 30.secs();
+            state = state.recv(Tick, now);
+
+            if step == 15 {
+                state = state.recv(KeypadUpdate(Key::ToggleLevels), now);
+            }
+        }
+
+        assert!(matches!(state, Running { .. }));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::key::Key;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use heapless::mpmc::Q16;
+
+/// how much gets logged, frames below the level set at
+/// build time with `DEFMT_LOG` are left out of the
+/// firmware altogether
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Self> {
+        use Level::*;
+
+        match name {
+            "trace" => Some(Trace),
+            "debug" => Some(Debug),
+            "info" => Some(Info),
+            "warn" => Some(Warn),
+            "error" => Some(Error),
+            _ => None,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+/// whether events are copied to the serial port
+static MIRROR: AtomicBool = AtomicBool::new(false);
+static MIRRORED: Q16<Event> = Q16::new();
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn set_mirror(mirror: bool) {
+    MIRROR.store(mirror, Ordering::Relaxed);
+}
+
+// exported from the crate root so the firmware can use
+// them too, and brought back in here under their names
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_at {
+    ($level:ident, $defmt:ident, $($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::$level) {
+            defmt::$defmt!($($arg)*);
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log_at!(Debug, debug, $($arg)*) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log_at!(Info, info, $($arg)*) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log_at!(Warn, warn, $($arg)*) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log_at!(Error, error, $($arg)*) };
+}
+
+// `warn` on its own clashes with the lint attribute
+pub use crate::{log_debug as debug, log_error as error, log_info as info, log_warn as warn};
+
+/// the things worth knowing about on a unit, logged as
+/// typed frames and copied to the serial port
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub enum Event {
+    /// the state machine moved to the named state
+    State(&'static str),
+    KeyPressed(Key),
+    KeyHeld(Key),
+    Fault {
+        active: bool,
+    },
+    SettingsSaved,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::State(name) => write!(f, "state {}", name),
+            Event::KeyPressed(key) => write!(f, "key-pressed {:?}", key),
+            Event::KeyHeld(key) => write!(f, "key-held {:?}", key),
+            Event::Fault { active: true } => write!(f, "fault"),
+            Event::Fault { active: false } => write!(f, "fault-cleared"),
+            Event::SettingsSaved => write!(f, "settings-saved"),
+        }
+    }
+}
+
+pub fn event(event: Event) {
+    info!("{}", event);
+
+    if MIRROR.load(Ordering::Relaxed) {
+        MIRRORED.enqueue(event).ok();
+    }
+}
+
+/// the next event waiting to go out on the serial port
+pub fn mirrored() -> Option<Event> {
+    MIRRORED.dequeue()
+}
@@ -0,0 +1,342 @@
+use crate::key::{Key, Keymap};
+use crate::scale::{Ballistics, FallRate, MeterScale};
+use crate::settings::Settings;
+use crate::{elapsed, EncoderTarget, TimeInstant, METER_SEGMENTS};
+
+/// how long the menu stays open without a key press
+pub const MENU_TIMEOUT_MS: u32 = 10_000;
+/// the auto dim timeouts the menu steps through, in
+/// minutes with zero for off
+const AUTO_DIM_MINUTES: [u8; 8] = [0, 1, 2, 5, 10, 15, 30, 60];
+/// the auto standby timeouts the menu steps through, in
+/// minutes with zero for off
+const AUTO_STANDBY_MINUTES: [u8; 6] = [0, 15, 30, 60, 120, 240];
+const METER_SCALES: [MeterScale; 2] = [MeterScale::Wide, MeterScale::Narrow];
+const BALLISTICS: [Ballistics; 2] = [Ballistics::Peak, Ballistics::Even];
+const FALL_RATES: [FallRate; 3] = [FallRate::Instant, FallRate::Fast, FallRate::Slow];
+const KEYMAPS: [Keymap; 2] = [Keymap::Standard, Keymap::Flipped];
+/// how far a step in the menu moves the brightness
+const BRIGHTNESS_STEP: u8 = 10;
+
+/// the settings the menu can change, in the order
+/// they're shown up the left bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuItem {
+    Scale,
+    Ballistics,
+    /// peak hold, showing the peak dot or not
+    Peaks,
+    FallRate,
+    AutoStandby,
+    AutoDim,
+    Keymap,
+    Levels,
+    Brightness,
+    HeadphoneDetect,
+    Encoder,
+}
+
+pub const MENU_ITEMS: [MenuItem; 11] = [
+    MenuItem::Scale,
+    MenuItem::Ballistics,
+    MenuItem::Peaks,
+    MenuItem::FallRate,
+    MenuItem::AutoStandby,
+    MenuItem::AutoDim,
+    MenuItem::Keymap,
+    MenuItem::Levels,
+    MenuItem::Brightness,
+    MenuItem::HeadphoneDetect,
+    MenuItem::Encoder,
+];
+
+// the left bar has a segment for each item
+const _: () = assert!(METER_SEGMENTS >= MENU_ITEMS.len());
+
+impl MenuItem {
+    pub fn name(self) -> &'static str {
+        use MenuItem::*;
+
+        match self {
+            Scale => "scale",
+            Ballistics => "ballistics",
+            Peaks => "peak-hold",
+            FallRate => "fall-rate",
+            AutoStandby => "auto-standby",
+            AutoDim => "auto-dim",
+            Keymap => "keymap",
+            Levels => "levels",
+            Brightness => "brightness",
+            HeadphoneDetect => "headphone-detect",
+            Encoder => "encoder",
+        }
+    }
+
+    /// how far along its range the item's value is, from
+    /// zero to one, for showing as a bar
+    pub fn level(self, settings: &Settings) -> f32 {
+        use MenuItem::*;
+
+        let flag = |value: bool| if value { 1.0 } else { 0.0 };
+
+        match self {
+            Scale => choice_level(&METER_SCALES, settings.meter_scale),
+            Ballistics => choice_level(&BALLISTICS, settings.ballistics),
+            Peaks => flag(settings.peaks),
+            FallRate => choice_level(&FALL_RATES, settings.fall_rate),
+            AutoStandby => minutes_level(&AUTO_STANDBY_MINUTES, settings.auto_standby_minutes),
+            AutoDim => minutes_level(&AUTO_DIM_MINUTES, settings.auto_dim.timeout_minutes),
+            Keymap => choice_level(&KEYMAPS, settings.keymap),
+            Levels => flag(settings.levels),
+            Brightness => settings.brightness as f32 / 100.0,
+            HeadphoneDetect => flag(settings.headphone_detect),
+            Encoder => flag(settings.encoder == EncoderTarget::Brightness),
+        }
+    }
+
+    /// move the item's value a step up or down, on and off
+    /// items are toggled either way
+    fn step(self, settings: &mut Settings, up: bool) {
+        use MenuItem::*;
+
+        match self {
+            Scale => settings.meter_scale = step_choice(&METER_SCALES, settings.meter_scale, up),
+            Ballistics => settings.ballistics = step_choice(&BALLISTICS, settings.ballistics, up),
+            Peaks => settings.peaks = !settings.peaks,
+            FallRate => settings.fall_rate = step_choice(&FALL_RATES, settings.fall_rate, up),
+            AutoStandby => {
+                settings.auto_standby_minutes =
+                    step_minutes(&AUTO_STANDBY_MINUTES, settings.auto_standby_minutes, up)
+            }
+            AutoDim => {
+                settings.auto_dim.timeout_minutes =
+                    step_minutes(&AUTO_DIM_MINUTES, settings.auto_dim.timeout_minutes, up)
+            }
+            Keymap => settings.keymap = step_choice(&KEYMAPS, settings.keymap, up),
+            Levels => settings.levels = !settings.levels,
+            Brightness => {
+                settings.auto_brightness = false;
+                settings.brightness = if up {
+                    settings.brightness.saturating_add(BRIGHTNESS_STEP).min(100)
+                } else {
+                    settings.brightness.saturating_sub(BRIGHTNESS_STEP)
+                };
+            }
+            HeadphoneDetect => settings.headphone_detect = !settings.headphone_detect,
+            Encoder => {
+                settings.encoder = match settings.encoder {
+                    EncoderTarget::Volume => EncoderTarget::Brightness,
+                    EncoderTarget::Brightness => EncoderTarget::Volume,
+                }
+            }
+        }
+    }
+}
+
+/// `index` moved a step along `len` choices, stopping
+/// at either end
+fn step_index(index: usize, len: usize, up: bool) -> usize {
+    if up {
+        (index + 1).min(len - 1)
+    } else {
+        index.saturating_sub(1)
+    }
+}
+
+fn step_choice<T: Copy + PartialEq>(choices: &[T], value: T, up: bool) -> T {
+    let index = choices
+        .iter()
+        .position(|choice| *choice == value)
+        .unwrap_or(0);
+
+    choices[step_index(index, choices.len(), up)]
+}
+
+fn choice_level<T: PartialEq>(choices: &[T], value: T) -> f32 {
+    let index = choices
+        .iter()
+        .position(|choice| *choice == value)
+        .unwrap_or(0);
+
+    index as f32 / (choices.len() - 1) as f32
+}
+
+/// the nearest step at or below `minutes`
+fn minutes_index(steps: &[u8], minutes: u8) -> usize {
+    steps.iter().rposition(|step| *step <= minutes).unwrap_or(0)
+}
+
+fn step_minutes(steps: &[u8], minutes: u8, up: bool) -> u8 {
+    steps[step_index(minutes_index(steps, minutes), steps.len(), up)]
+}
+
+fn minutes_level(steps: &[u8], minutes: u8) -> f32 {
+    minutes_index(steps, minutes) as f32 / (steps.len() - 1) as f32
+}
+
+/// what the keys do while the menu is open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuKey {
+    Up,
+    Down,
+    Select,
+    Back,
+}
+
+impl MenuKey {
+    pub fn from_key(key: Key) -> Option<Self> {
+        match key {
+            Key::BrightnessUp => Some(MenuKey::Up),
+            Key::BrightnessDown => Some(MenuKey::Down),
+            Key::TogglePeaks => Some(MenuKey::Select),
+            Key::ToggleLevels => Some(MenuKey::Back),
+            _ => None,
+        }
+    }
+}
+
+/// browsing the menu items, or editing one of them
+#[derive(Debug, Clone, Copy)]
+pub struct MenuPosition {
+    pub item: usize,
+    pub editing: bool,
+    last_key: TimeInstant,
+}
+
+impl MenuPosition {
+    pub fn new(now: TimeInstant) -> Self {
+        Self {
+            item: 0,
+            editing: false,
+            last_key: now,
+        }
+    }
+
+    pub fn item(&self) -> MenuItem {
+        MENU_ITEMS[self.item]
+    }
+
+    /// act on a key, returns false once it has backed
+    /// out of the menu
+    pub fn key(&mut self, key: MenuKey, settings: &mut Settings, now: TimeInstant) -> bool {
+        use MenuKey::*;
+
+        self.last_key = now;
+
+        match (self.editing, key) {
+            (false, Up) => self.item = (self.item + 1).min(MENU_ITEMS.len() - 1),
+            (false, Down) => self.item = self.item.saturating_sub(1),
+            (false, Select) => self.editing = true,
+            (false, Back) => return false,
+            (true, Up) => self.item().step(settings, true),
+            (true, Down) => self.item().step(settings, false),
+            (true, Select | Back) => self.editing = false,
+        }
+
+        true
+    }
+
+    pub fn timed_out(&self, now: TimeInstant) -> bool {
+        elapsed(self.last_key, now).to_millis() >= MENU_TIMEOUT_MS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fugit::ExtU32;
+    use MenuKey::*;
+
+    fn at(ms: u32) -> TimeInstant {
+        TimeInstant::from_ticks(0) + ms.millis()
+    }
+
+    /// press each of `keys` in turn, returns whether the
+    /// menu is still open
+    fn press(position: &mut MenuPosition, settings: &mut Settings, keys: &[MenuKey]) -> bool {
+        keys.iter().all(|key| position.key(*key, settings, at(0)))
+    }
+
+    #[test]
+    fn browsing_stops_at_either_end() {
+        let mut settings = Settings::default();
+        let mut position = MenuPosition::new(at(0));
+
+        press(&mut position, &mut settings, &[Down]);
+
+        assert_eq!(position.item(), MenuItem::Scale);
+
+        press(&mut position, &mut settings, &[Up; 20]);
+
+        assert_eq!(position.item(), MenuItem::Encoder);
+    }
+
+    #[test]
+    fn edits_the_selected_item() {
+        let mut settings = Settings::default();
+        let mut position = MenuPosition::new(at(0));
+
+        press(&mut position, &mut settings, &[Up, Up, Up, Select]);
+
+        assert_eq!(position.item(), MenuItem::FallRate);
+        assert!(position.editing);
+
+        press(&mut position, &mut settings, &[Up, Up, Up]);
+
+        assert_eq!(settings.fall_rate, FallRate::Slow);
+        assert_eq!(MenuItem::FallRate.level(&settings), 1.0);
+
+        press(&mut position, &mut settings, &[Down]);
+
+        assert_eq!(settings.fall_rate, FallRate::Fast);
+        assert_eq!(MenuItem::FallRate.level(&settings), 0.5);
+    }
+
+    #[test]
+    fn steps_through_the_timeouts() {
+        let mut settings = Settings::default();
+
+        MenuItem::AutoStandby.step(&mut settings, true);
+        MenuItem::AutoStandby.step(&mut settings, true);
+
+        assert_eq!(settings.auto_standby_minutes, 30);
+
+        // a timeout set over the console between steps
+        // moves on from the step below it
+        settings.auto_dim.timeout_minutes = 7;
+        MenuItem::AutoDim.step(&mut settings, true);
+
+        assert_eq!(settings.auto_dim.timeout_minutes, 10);
+    }
+
+    #[test]
+    fn changes_the_keymap() {
+        let mut settings = Settings::default();
+
+        MenuItem::Keymap.step(&mut settings, true);
+
+        assert_eq!(settings.keymap, Keymap::Flipped);
+        assert_eq!(settings.keymap.keys()[0], Key::CycleInput);
+    }
+
+    #[test]
+    fn back_leaves_once_not_editing() {
+        let mut settings = Settings::default();
+        let mut position = MenuPosition::new(at(0));
+
+        assert!(press(&mut position, &mut settings, &[Select, Back]));
+        assert!(!position.editing);
+        assert!(!press(&mut position, &mut settings, &[Back]));
+    }
+
+    #[test]
+    fn times_out_from_the_last_key() {
+        let mut settings = Settings::default();
+        let mut position = MenuPosition::new(at(0));
+
+        position.key(Up, &mut settings, at(5000));
+
+        assert!(!position.timed_out(at(5000 + MENU_TIMEOUT_MS - 1)));
+        assert!(position.timed_out(at(5000 + MENU_TIMEOUT_MS)));
+    }
+}
@@ -0,0 +1,320 @@
+use crate::modulation::{Frame, MAX_INTENSITY};
+use crate::{State, State::*, TimeInstant, METER_CHANNELS, METER_SEGMENTS};
+
+/// intensity of the segments that make up the level bar
+const LEVEL_INTENSITY: u8 = MAX_INTENSITY;
+/// intensity of the peak dot, kept dimmer than the bar
+/// so it reads as a marker rather than part of the level
+const PEAK_INTENSITY: u8 = MAX_INTENSITY / 2;
+
+/// a bit per segment of a channel, from the bottom up
+pub type ChannelMask = u32;
+/// a mask for each channel
+pub type SegmentMask<const CHANNELS: usize = METER_CHANNELS> = [ChannelMask; CHANNELS];
+
+// every channel's segments fit in its mask
+const _: () = assert!(METER_SEGMENTS <= ChannelMask::BITS as usize);
+
+/// whether segment `index` is set in `mask`, anything
+/// past the top of the mask isn't
+pub fn is_lit(mask: ChannelMask, index: usize) -> bool {
+    u32::try_from(index)
+        .ok()
+        .and_then(|index| mask.checked_shr(index))
+        .is_some_and(|mask| mask & 1 > 0)
+}
+
+/// the bottom `lit` segments
+pub fn bar(lit: usize) -> ChannelMask {
+    u32::try_from(lit)
+        .ok()
+        .and_then(|lit| (1 as ChannelMask).checked_shl(lit))
+        .map_or(ChannelMask::MAX, |top| top - 1)
+}
+
+/// just the top one of the bottom `lit` segments
+pub fn dot(lit: usize) -> ChannelMask {
+    bar(lit) & !bar(lit.saturating_sub(1))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MeterChannel {
+    pub level: ChannelMask,
+    pub level_decay: TimeInstant,
+    pub peak: ChannelMask,
+    pub peak_decay: TimeInstant,
+    /// intensity of the segment above the level, used to
+    /// interpolate between two segments
+    pub partial: u8,
+    pub calculated: usize,
+}
+
+impl MeterChannel {
+    pub fn new(now: TimeInstant) -> Self {
+        Self {
+            calculated: 0,
+            level: 0,
+            level_decay: now,
+            peak: 0,
+            peak_decay: now,
+            partial: 0,
+        }
+    }
+
+    /// intensity of each segment, from the bottom of
+    /// the channel up
+    fn segments<const SEGMENTS: usize>(&self, peaks: bool, levels: bool) -> [u8; SEGMENTS] {
+        let mut result = [0; SEGMENTS];
+
+        for (index, intensity) in result.iter_mut().enumerate() {
+            if levels && is_lit(self.level, index) {
+                *intensity = LEVEL_INTENSITY;
+            }
+
+            if peaks && is_lit(self.peak, index) {
+                *intensity = (*intensity).max(PEAK_INTENSITY);
+            }
+        }
+
+        if levels {
+            if let Some(intensity) = result.get_mut(self.level.count_ones() as usize) {
+                *intensity = (*intensity).max(self.partial);
+            }
+        }
+
+        result
+    }
+}
+
+/// what the meter shows for `state`
+pub fn intensities<const SEGMENTS: usize, const CHANNELS: usize>(
+    state: &State,
+) -> Frame<SEGMENTS, CHANNELS> {
+    let mut result = [[0; SEGMENTS]; CHANNELS];
+
+    if let Running { settings, channels } = state {
+        for (result, channel) in result.iter_mut().zip(channels) {
+            *result = channel.segments(settings.peaks, settings.levels);
+        }
+    }
+
+    // the first bar shows the item and the second its
+    // value, whichever is being changed is brighter
+    if let Menu { settings, position } = state {
+        let item = position.item();
+        let value = (item.level(settings) * SEGMENTS as f32 + 0.5) as usize;
+        let (item_intensity, value_intensity) = if position.editing {
+            (PEAK_INTENSITY, MAX_INTENSITY)
+        } else {
+            (MAX_INTENSITY, PEAK_INTENSITY)
+        };
+
+        let mut bars = result.iter_mut();
+
+        if let Some(intensity) = bars.next().and_then(|bar| bar.get_mut(position.item)) {
+            *intensity = item_intensity;
+        }
+
+        for intensity in bars.next().into_iter().flatten().take(value) {
+            *intensity = value_intensity;
+        }
+    }
+
+    result
+}
+
+/// light one segment per input number on every channel
+pub fn flash<const SEGMENTS: usize, const CHANNELS: usize>(
+    audio_input: u8,
+) -> Frame<SEGMENTS, CHANNELS> {
+    let mut result = [[0; SEGMENTS]; CHANNELS];
+
+    for channel in result.iter_mut() {
+        for intensity in channel.iter_mut().take(audio_input as usize + 1) {
+            *intensity = MAX_INTENSITY;
+        }
+    }
+
+    result
+}
+
+/// light the self test's segments
+pub fn segments<const SEGMENTS: usize, const CHANNELS: usize>(
+    lit: &SegmentMask<CHANNELS>,
+) -> Frame<SEGMENTS, CHANNELS> {
+    let mut result = [[0; SEGMENTS]; CHANNELS];
+
+    for (intensities, lit) in result.iter_mut().zip(lit) {
+        for (index, intensity) in intensities.iter_mut().enumerate() {
+            if is_lit(*lit, index) {
+                *intensity = MAX_INTENSITY;
+            }
+        }
+    }
+
+    result
+}
+
+/// every other segment lit, out of step between
+/// neighbouring channels
+pub fn fault<const SEGMENTS: usize, const CHANNELS: usize>() -> Frame<SEGMENTS, CHANNELS> {
+    let mut result = [[0; SEGMENTS]; CHANNELS];
+
+    for (channel, intensities) in result.iter_mut().enumerate() {
+        for (segment, intensity) in intensities.iter_mut().enumerate() {
+            if (channel + segment) % 2 == 1 {
+                *intensity = MAX_INTENSITY;
+            }
+        }
+    }
+
+    result
+}
+
+/// the order segments are shifted out in. the first bit
+/// ends up at the far end of the chain, so that's the
+/// top of the last channel, working down each channel
+/// and back towards the first
+pub fn chain<const SEGMENTS: usize, const CHANNELS: usize>(
+    lit: &[[bool; SEGMENTS]; CHANNELS],
+) -> impl Iterator<Item = bool> + '_ {
+    lit.iter()
+        .rev()
+        .flat_map(|channel| channel.iter().rev().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_to_input;
+    use crate::modulation::Modulator;
+    use crate::scale::{Ballistics, FallRate, MeterScale, Scale};
+    use std::vec::Vec;
+
+    #[test]
+    fn masks_shift_safely() {
+        assert_eq!(bar(0), 0);
+        assert_eq!(bar(3), 0b111);
+        assert_eq!(bar(32), ChannelMask::MAX);
+        assert_eq!(bar(40), ChannelMask::MAX);
+        assert_eq!(dot(0), 0);
+        assert_eq!(dot(3), 0b100);
+        assert_eq!(dot(32), 1 << 31);
+        assert!(is_lit(1 << 31, 31));
+        assert!(!is_lit(ChannelMask::MAX, 32));
+        assert!(!is_lit(ChannelMask::MAX, usize::MAX));
+    }
+
+    #[test]
+    fn full_scale_lights_every_segment() {
+        fn check<const SEGMENTS: usize>() {
+            let scale =
+                Scale::<SEGMENTS>::new(MeterScale::Wide, 0, Ballistics::Peak, FallRate::Instant);
+            let (lit, _) = scale.find(db_to_input(20.0));
+            let channel = MeterChannel {
+                level: bar(lit),
+                peak: dot(lit),
+                ..MeterChannel::new(TimeInstant::from_ticks(0))
+            };
+
+            assert_eq!(lit, SEGMENTS);
+            assert_eq!(
+                channel.segments::<SEGMENTS>(false, true),
+                [LEVEL_INTENSITY; SEGMENTS]
+            );
+            assert_eq!(
+                channel.segments::<SEGMENTS>(true, false)[SEGMENTS - 1],
+                PEAK_INTENSITY
+            );
+        }
+
+        check::<8>();
+        check::<12>();
+        check::<20>();
+        check::<32>();
+    }
+
+    #[test]
+    fn chain_starts_at_the_top_of_the_last_channel() {
+        fn check<const SEGMENTS: usize, const CHANNELS: usize>() {
+            let mut lit = [[false; SEGMENTS]; CHANNELS];
+
+            lit[CHANNELS - 1][SEGMENTS - 1] = true;
+            lit[0][0] = true;
+
+            let bits: Vec<bool> = chain(&lit).collect();
+
+            assert_eq!(bits.len(), SEGMENTS * CHANNELS);
+            assert!(bits[0], "{}x{}", CHANNELS, SEGMENTS);
+            assert!(bits[SEGMENTS * CHANNELS - 1], "{}x{}", CHANNELS, SEGMENTS);
+            assert_eq!(bits.iter().filter(|bit| **bit).count(), 2);
+        }
+
+        check::<12, 2>();
+        check::<12, 6>();
+        check::<20, 8>();
+        check::<32, 8>();
+    }
+
+    #[test]
+    fn chain_runs_down_each_channel() {
+        let lit = [[false, true, false], [true, false, false]];
+
+        assert_eq!(
+            chain(&lit).collect::<Vec<_>>(),
+            [false, false, true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn self_test_masks_light_by_channel() {
+        let frame = segments::<12, 3>(&[dot(1), 0, bar(12)]);
+
+        assert_eq!(frame[0][0], MAX_INTENSITY);
+        assert!(frame[0][1..].iter().all(|intensity| *intensity == 0));
+        assert!(frame[1].iter().all(|intensity| *intensity == 0));
+        assert!(frame[2].iter().all(|intensity| *intensity == MAX_INTENSITY));
+    }
+
+    #[test]
+    fn six_channels_pack_into_one_chain() {
+        let mut modulator = Modulator::<12, 6>::new();
+        let mut frame = [[0; 12]; 6];
+
+        // each channel lit a segment higher than the last
+        for (channel, intensities) in frame.iter_mut().enumerate() {
+            for intensity in intensities.iter_mut().take(channel + 1) {
+                *intensity = MAX_INTENSITY;
+            }
+        }
+
+        modulator.show(frame);
+
+        let lit = modulator.next_frame();
+        let bits: Vec<bool> = chain(&lit).collect();
+
+        assert_eq!(bits.len(), 72);
+
+        // the last channel goes out first, top down
+        for (position, channel) in (0..6).rev().enumerate() {
+            let expected: Vec<bool> = (0..12).rev().map(|segment| segment <= channel).collect();
+
+            assert_eq!(
+                bits[position * 12..][..12],
+                expected[..],
+                "channel {}",
+                channel
+            );
+        }
+    }
+
+    #[test]
+    fn six_channel_fault_alternates_between_neighbours() {
+        let frame = fault::<12, 6>();
+
+        for channel in 0..6 {
+            assert_eq!(frame[channel][0] > 0, channel % 2 == 1);
+            assert_eq!(frame[channel][1] > 0, channel % 2 == 0);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+/// how many bit planes each segment intensity is
+/// split into.
+///
+/// every extra bit doubles the frames in a cycle, and
+/// so the edges that have to be shifted out per cycle.
+pub const MODULATION_BITS: u32 = 3;
+/// the brightest intensity a segment can be given
+pub const MAX_INTENSITY: u8 = (1 << MODULATION_BITS) - 1;
+/// the number of frames shifted out per cycle, bit
+/// plane `n` is latched for `1 << n` of them
+pub const MODULATION_FRAMES: usize = (1 << MODULATION_BITS) - 1;
+/// the fewest cycles a second the segments are shown
+/// at, well clear of visible flicker
+pub const MIN_REFRESH_HZ: u32 = 200;
+
+/// the edges it takes to shift a frame into a chain of
+/// `bits`, two per bit then a reset and the latch
+pub const fn frame_edges(bits: usize) -> u32 {
+    2 * bits as u32 + 3
+}
+
+/// how many edges to shift out on each clock tick so a
+/// chain of `bits` is refreshed at least
+/// `MIN_REFRESH_HZ`, when ticking at `tick_hz`
+pub const fn edges_per_tick(bits: usize, tick_hz: u32) -> u32 {
+    let ticks_per_frame = tick_hz / (MIN_REFRESH_HZ * MODULATION_FRAMES as u32);
+    let ticks_per_frame = if ticks_per_frame == 0 {
+        1
+    } else {
+        ticks_per_frame
+    };
+
+    frame_edges(bits).div_ceil(ticks_per_frame)
+}
+
+/// the ticks it takes to shift a frame into a chain of
+/// `bits` when ticking at `tick_hz`
+pub const fn frame_ticks(bits: usize, tick_hz: u32) -> u32 {
+    frame_edges(bits).div_ceil(edges_per_tick(bits, tick_hz))
+}
+
+/// the cycles a second a chain of `bits` is refreshed
+/// at when ticking at `tick_hz`
+pub const fn refresh_hz(bits: usize, tick_hz: u32) -> u32 {
+    tick_hz / (frame_ticks(bits, tick_hz) * MODULATION_FRAMES as u32)
+}
+
+/// an intensity for each segment of each channel, from
+/// the bottom of the channel up
+pub type Frame<const SEGMENTS: usize, const CHANNELS: usize> = [[u8; SEGMENTS]; CHANNELS];
+
+/// bit angle modulation of the meter's segments.
+///
+/// each segment gets its own intensity, which is split
+/// into bit planes that are latched for a number of
+/// frames matching their weight. the planes are
+/// interleaved so the most significant one is spread
+/// over the whole cycle instead of shown in one go.
+pub struct Modulator<const SEGMENTS: usize, const CHANNELS: usize> {
+    target: Frame<SEGMENTS, CHANNELS>,
+    current: Frame<SEGMENTS, CHANNELS>,
+    frame: usize,
+}
+
+impl<const SEGMENTS: usize, const CHANNELS: usize> Modulator<SEGMENTS, CHANNELS> {
+    pub fn new() -> Self {
+        Self {
+            target: [[0; SEGMENTS]; CHANNELS],
+            current: [[0; SEGMENTS]; CHANNELS],
+            frame: 0,
+        }
+    }
+
+    /// set the intensity each segment should move to,
+    /// rising segments jump straight there while falling
+    /// ones fade out one step per cycle.
+    pub fn set(&mut self, target: Frame<SEGMENTS, CHANNELS>) {
+        for (intensity, target) in self
+            .target
+            .iter_mut()
+            .flatten()
+            .zip(target.iter().flatten())
+        {
+            *intensity = (*target).min(MAX_INTENSITY);
+        }
+    }
+
+    /// set the intensity of each segment straight away,
+    /// without fading
+    pub fn show(&mut self, target: Frame<SEGMENTS, CHANNELS>) {
+        self.set(target);
+        self.current = self.target;
+    }
+
+    /// which segments are lit for the next frame
+    pub fn next_frame(&mut self) -> [[bool; SEGMENTS]; CHANNELS] {
+        let plane = MODULATION_BITS - 1 - (self.frame as u32 + 1).trailing_zeros();
+        let mut lit = [[false; SEGMENTS]; CHANNELS];
+
+        for (lit, intensity) in lit.iter_mut().flatten().zip(self.current.iter().flatten()) {
+            *lit = intensity >> plane & 1 == 1;
+        }
+
+        self.frame += 1;
+
+        if self.frame == MODULATION_FRAMES {
+            self.frame = 0;
+            self.fade();
+        }
+
+        lit
+    }
+
+    fn fade(&mut self) {
+        for (current, target) in self
+            .current
+            .iter_mut()
+            .flatten()
+            .zip(self.target.iter().flatten())
+        {
+            if *current < *target {
+                *current = *target;
+            } else if *current > *target {
+                *current -= 1;
+            }
+        }
+    }
+}
+
+impl<const SEGMENTS: usize, const CHANNELS: usize> Default for Modulator<SEGMENTS, CHANNELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the frames a segment at each intensity is lit for
+    /// over one cycle, in order
+    fn pattern(intensity: u8) -> [bool; MODULATION_FRAMES] {
+        let mut modulator = Modulator::<1, 1>::new();
+        let mut pattern = [false; MODULATION_FRAMES];
+
+        modulator.show([[intensity]]);
+
+        for lit in pattern.iter_mut() {
+            *lit = modulator.next_frame()[0][0];
+        }
+
+        pattern
+    }
+
+    #[test]
+    fn lit_for_as_many_frames_as_the_intensity() {
+        for intensity in 0..=MAX_INTENSITY {
+            let lit = pattern(intensity).iter().filter(|lit| **lit).count();
+
+            assert_eq!(lit, intensity as usize, "intensity {}", intensity);
+        }
+    }
+
+    #[test]
+    fn planes_are_interleaved() {
+        // the top plane is shown on every other frame, the
+        // next on every fourth and the lowest once
+        assert_eq!(pattern(4), [true, false, true, false, true, false, true]);
+        assert_eq!(pattern(2), [false, true, false, false, false, true, false]);
+        assert_eq!(pattern(1), [false, false, false, true, false, false, false]);
+        assert_eq!(pattern(7), [true; MODULATION_FRAMES]);
+    }
+
+    #[test]
+    fn segments_are_modulated_apart() {
+        let mut modulator = Modulator::<3, 2>::new();
+
+        modulator.show([[0, 4, 7], [1, 2, 3]]);
+
+        assert_eq!(
+            modulator.next_frame(),
+            [[false, true, true], [false, false, false]]
+        );
+        assert_eq!(
+            modulator.next_frame(),
+            [[false, false, true], [false, true, true]]
+        );
+    }
+
+    #[test]
+    fn falling_segments_fade_a_step_per_cycle() {
+        let mut modulator = Modulator::<1, 1>::new();
+
+        modulator.show([[MAX_INTENSITY]]);
+        modulator.set([[0]]);
+
+        for expected in (0..MAX_INTENSITY).rev() {
+            let lit = (0..MODULATION_FRAMES)
+                .filter(|_| modulator.next_frame()[0][0])
+                .count();
+
+            assert_eq!(lit, expected as usize + 1);
+        }
+
+        modulator.set([[5]]);
+        modulator.next_frame();
+
+        // rising segments wait for the cycle to end, then
+        // jump straight to their target
+        let lit = (1..MODULATION_FRAMES)
+            .filter(|_| modulator.next_frame()[0][0])
+            .count();
+
+        assert_eq!(lit, 0);
+
+        let lit = (0..MODULATION_FRAMES)
+            .filter(|_| modulator.next_frame()[0][0])
+            .count();
+
+        assert_eq!(lit, 5);
+    }
+
+    #[test]
+    fn intensities_are_capped() {
+        let mut modulator = Modulator::<1, 1>::new();
+
+        modulator.show([[u8::MAX]]);
+
+        assert!((0..MODULATION_FRAMES).all(|_| modulator.next_frame()[0][0]));
+    }
+
+    #[test]
+    fn refresh_clears_flicker_at_every_size() {
+        for (segments, channels) in [(12, 2), (12, 6), (20, 8), (32, 8)] {
+            let hz = refresh_hz(segments * channels, 20_000);
+
+            assert!(
+                hz >= MIN_REFRESH_HZ,
+                "{}x{} refreshes at {}hz",
+                channels,
+                segments,
+                hz
+            );
+        }
+    }
+
+    #[test]
+    fn short_chains_need_few_edges_a_tick() {
+        assert_eq!(frame_edges(24), 51);
+        assert_eq!(edges_per_tick(24, 20_000), 4);
+        assert_eq!(edges_per_tick(160, 20_000), 24);
+    }
+
+    #[test]
+    fn frames_take_whole_ticks() {
+        assert_eq!(frame_ticks(24, 20_000), 13);
+        assert_eq!(frame_ticks(160, 20_000), 14);
+    }
+}
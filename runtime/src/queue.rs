@@ -0,0 +1,395 @@
+use crate::key::Key;
+use crate::meter::SegmentMask;
+use crate::Message::{self, *};
+use crate::METER_CHANNELS;
+use core::cell::RefCell;
+use critical_section::Mutex;
+use heapless::Deque;
+
+/// the keys counted once the input queue is full
+const COUNTED_KEYS: [Key; 9] = [
+    Key::ToggleBrightness,
+    Key::BrightnessUp,
+    Key::BrightnessDown,
+    Key::TogglePeaks,
+    Key::ToggleLevels,
+    Key::ToggleOutput,
+    Key::ToggleMute,
+    Key::CycleInput,
+    Key::Wake,
+];
+/// unassigned keys counted once the input queue is
+/// full, one for each row of the keypad
+const COUNTED_UNASSIGNED: usize = 8;
+const COUNTERS: usize = COUNTED_KEYS.len() + COUNTED_UNASSIGNED;
+
+/// the messages waiting to be handled, only touched in
+/// a critical section so cores without atomic
+/// read-modify-write, such as the cortex-m0+, can share
+/// them with interrupts too
+static QUEUES: Mutex<RefCell<Queues>> = Mutex::new(RefCell::new(Queues::new()));
+
+/// how the queues have coped since boot
+#[derive(Debug, Clone, Copy)]
+pub struct QueueStats {
+    /// settings lost to a full queue
+    pub dropped: u32,
+    /// key presses and encoder turns that didn't fit in
+    /// the input queue, and were counted instead
+    pub overflowed_input: u32,
+    /// meter updates replaced before they were handled
+    pub coalesced: u32,
+    /// the most each queue has held at once
+    pub input_high_water: u32,
+    pub other_high_water: u32,
+}
+
+struct Queues {
+    /// key presses and encoder turns, kept apart so a
+    /// burst of anything else can't push them out
+    input: Deque<Message, 32>,
+    /// key presses and holds that didn't fit in `input`,
+    /// counted for each key so none are lost, just their
+    /// order
+    presses: [u32; COUNTERS],
+    holds: [u32; COUNTERS],
+    /// the encoder turns that didn't fit, added up into
+    /// one net turn
+    turn: i32,
+    pushes: u32,
+    /// the settings sent over the console and control
+    /// interface
+    other: Deque<Message, 8>,
+    /// whether a fault has been raised since the last one
+    /// was handled, so one that comes and goes between
+    /// two handlings is still seen
+    fault_raised: bool,
+    /// the latest fault input, newer ones replace older
+    /// ones, as do the latest values of the other inputs
+    fault: Option<bool>,
+    headphones: Option<bool>,
+    ambient: Option<f32>,
+    booted: bool,
+    self_test_result: Option<SegmentMask>,
+    self_test_done: bool,
+    /// whether a tick is waiting, ticks only say time has
+    /// passed so any number of them are handled as one
+    tick: bool,
+    /// the latest meter levels, newer ones replace older
+    /// ones as only the latest is worth drawing
+    meter: Option<[f32; METER_CHANNELS]>,
+    stats: QueueStats,
+}
+
+impl Queues {
+    const fn new() -> Self {
+        Self {
+            input: Deque::new(),
+            presses: [0; COUNTERS],
+            holds: [0; COUNTERS],
+            turn: 0,
+            pushes: 0,
+            other: Deque::new(),
+            fault_raised: false,
+            fault: None,
+            headphones: None,
+            ambient: None,
+            booted: false,
+            self_test_result: None,
+            self_test_done: false,
+            tick: false,
+            meter: None,
+            stats: QueueStats {
+                dropped: 0,
+                overflowed_input: 0,
+                coalesced: 0,
+                input_high_water: 0,
+                other_high_water: 0,
+            },
+        }
+    }
+
+    fn push(&mut self, msg: Message) {
+        let stats = &mut self.stats;
+
+        match msg {
+            MeterUpdate(levels) => {
+                if self.meter.replace(levels).is_some() {
+                    stats.coalesced += 1;
+                }
+            }
+            Tick => self.tick = true,
+            Booted => self.booted = true,
+            FaultUpdate(active) => {
+                // raised along with the latest value, so a
+                // fault that's already cleared is still
+                // handled first
+                self.fault_raised |= active;
+                self.fault = Some(active);
+            }
+            HeadphonesInserted(inserted) => self.headphones = Some(inserted),
+            AmbientUpdate(lux) => self.ambient = Some(lux),
+            SelfTestResult(dead) => self.self_test_result = Some(dead),
+            SelfTestDone => self.self_test_done = true,
+            KeypadUpdate(_) | KeypadHold(_) | EncoderTurn(_) | EncoderPush => {
+                if self.input.push_back(msg).is_ok() {
+                    stats.input_high_water = stats.input_high_water.max(self.input.len() as u32);
+
+                    return;
+                }
+
+                stats.overflowed_input += 1;
+
+                match msg {
+                    KeypadUpdate(key) => self.presses[counter(key)] += 1,
+                    KeypadHold(key) => self.holds[counter(key)] += 1,
+                    EncoderTurn(direction) => {
+                        self.turn = self.turn.saturating_add(direction as i32)
+                    }
+                    _ => self.pushes += 1,
+                }
+            }
+            _ => {
+                if self.other.push_back(msg).is_ok() {
+                    stats.other_high_water = stats.other_high_water.max(self.other.len() as u32);
+                } else {
+                    stats.dropped += 1;
+                }
+            }
+        }
+    }
+
+    /// the next input that overflowed into the counters
+    fn overflowed_input(&mut self) -> Option<Message> {
+        for counter in 0..COUNTERS {
+            if take(&mut self.presses[counter]) {
+                return Some(KeypadUpdate(counted_key(counter)));
+            }
+
+            if take(&mut self.holds[counter]) {
+                return Some(KeypadHold(counted_key(counter)));
+            }
+        }
+
+        if take(&mut self.pushes) {
+            return Some(EncoderPush);
+        }
+
+        // as much of the net turn as fits, the rest is
+        // left for next time
+        let direction = self.turn.clamp(i8::MIN as i32, i8::MAX as i32);
+
+        self.turn -= direction;
+
+        (direction != 0).then_some(EncoderTurn(direction as i8))
+    }
+
+    fn next(&mut self) -> Option<Message> {
+        if core::mem::take(&mut self.fault_raised) {
+            // already handled if it's still raised
+            if self.fault == Some(true) {
+                self.fault = None;
+            }
+
+            return Some(FaultUpdate(true));
+        }
+
+        if let Some(active) = self.fault.take() {
+            return Some(FaultUpdate(active));
+        }
+
+        if let Some(msg) = self.input.pop_front() {
+            return Some(msg);
+        }
+
+        if let Some(msg) = self.overflowed_input() {
+            return Some(msg);
+        }
+
+        if core::mem::take(&mut self.booted) {
+            return Some(Booted);
+        }
+
+        // the result has to be in before the test finishes
+        if let Some(dead) = self.self_test_result.take() {
+            return Some(SelfTestResult(dead));
+        }
+
+        if core::mem::take(&mut self.self_test_done) {
+            return Some(SelfTestDone);
+        }
+
+        if let Some(inserted) = self.headphones.take() {
+            return Some(HeadphonesInserted(inserted));
+        }
+
+        if let Some(msg) = self.other.pop_front() {
+            return Some(msg);
+        }
+
+        if let Some(lux) = self.ambient.take() {
+            return Some(AmbientUpdate(lux));
+        }
+
+        if core::mem::take(&mut self.tick) {
+            return Some(Tick);
+        }
+
+        self.meter.take().map(MeterUpdate)
+    }
+}
+
+/// the counter kept for `key`
+fn counter(key: Key) -> usize {
+    match key {
+        Key::Unassigned(num) => COUNTED_KEYS.len() + num.min(COUNTED_UNASSIGNED - 1),
+        key => COUNTED_KEYS
+            .iter()
+            .position(|counted| *counted == key)
+            .unwrap_or(0),
+    }
+}
+
+/// the key counted by `counter`
+fn counted_key(counter: usize) -> Key {
+    COUNTED_KEYS
+        .get(counter)
+        .copied()
+        .unwrap_or_else(|| Key::Unassigned(counter - COUNTED_KEYS.len()))
+}
+
+/// take one off `count`, returns false if it was empty
+fn take(count: &mut u32) -> bool {
+    match count.checked_sub(1) {
+        Some(less) => {
+            *count = less;
+
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn push(msg: Message) {
+    critical_section::with(|cs| QUEUES.borrow_ref_mut(cs).push(msg));
+}
+
+/// the next message to handle, a raised fault first,
+/// then input, then everything else, the tick and the
+/// meter last
+pub fn next() -> Option<Message> {
+    critical_section::with(|cs| QUEUES.borrow_ref_mut(cs).next())
+}
+
+pub fn stats() -> QueueStats {
+    critical_section::with(|cs| QUEUES.borrow_ref(cs).stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    /// the queues are shared, so the tests take turns
+    static TURNS: Mutex<()> = Mutex::new(());
+
+    fn drain() -> Vec<Message> {
+        core::iter::from_fn(next).collect()
+    }
+
+    #[test]
+    fn keys_survive_meter_floods() {
+        let _queues = TURNS.lock().unwrap_or_else(|error| error.into_inner());
+
+        drain();
+
+        for update in 0..1000 {
+            MeterUpdate([0.5; METER_CHANNELS]).send();
+
+            if update % 10 == 0 {
+                KeypadUpdate(Key::BrightnessUp).send();
+                KeypadHold(Key::ToggleMute).send();
+            }
+        }
+
+        let msgs = drain();
+        let presses = msgs
+            .iter()
+            .filter(|msg| matches!(msg, KeypadUpdate(Key::BrightnessUp)))
+            .count();
+        let holds = msgs
+            .iter()
+            .filter(|msg| matches!(msg, KeypadHold(Key::ToggleMute)))
+            .count();
+        let meter = msgs
+            .iter()
+            .filter(|msg| matches!(msg, MeterUpdate(_)))
+            .count();
+
+        assert_eq!((presses, holds, meter), (100, 100, 1));
+    }
+
+    #[test]
+    fn overflowed_turns_add_up() {
+        let _queues = TURNS.lock().unwrap_or_else(|error| error.into_inner());
+
+        drain();
+
+        for _ in 0..200 {
+            EncoderTurn(100).send();
+        }
+
+        EncoderTurn(-7).send();
+
+        let turned: i32 = drain()
+            .iter()
+            .map(|msg| match msg {
+                EncoderTurn(direction) => *direction as i32,
+                _ => 0,
+            })
+            .sum();
+
+        assert_eq!(turned, 200 * 100 - 7);
+    }
+
+    #[test]
+    fn a_passing_fault_is_still_seen() {
+        let _queues = TURNS.lock().unwrap_or_else(|error| error.into_inner());
+
+        drain();
+
+        FaultUpdate(true).send();
+        FaultUpdate(false).send();
+
+        assert!(matches!(
+            drain()[..],
+            [FaultUpdate(true), FaultUpdate(false)]
+        ));
+
+        FaultUpdate(true).send();
+
+        assert!(matches!(drain()[..], [FaultUpdate(true)]));
+    }
+
+    #[test]
+    fn self_test_results_come_before_it_finishes() {
+        let _queues = TURNS.lock().unwrap_or_else(|error| error.into_inner());
+
+        drain();
+
+        SelfTestDone.send();
+        SelfTestResult([0b101; METER_CHANNELS]).send();
+        HeadphonesInserted(true).send();
+        HeadphonesInserted(false).send();
+
+        assert!(matches!(
+            drain()[..],
+            [
+                SelfTestResult([0b101, ..]),
+                SelfTestDone,
+                HeadphonesInserted(false)
+            ]
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+use crate::modulation::MAX_INTENSITY;
+use crate::{db_to_input, DB_MINUS_INF};
+
+/// the furthest the reference level can be moved from
+/// the nominal level, in db either way
+pub const REFERENCE_DB_LIMIT: i8 = 20;
+
+/// the rev1 meter's 12 segments, in db, and how long a
+/// peak is held on each. the steps close up around
+/// nominal, and overs are held longer so they stand out
+const REV1: [(f32, u32); 12] = [
+    (-54.0, 300),
+    (-45.0, 300),
+    (-36.0, 300),
+    (-27.0, 300),
+    (-18.0, 300),
+    (-12.0, 300),
+    (-6.0, 300),
+    (-3.0, 300),
+    (0.0, 600),
+    (3.0, 900),
+    (6.0, 1500),
+    (12.0, 2400),
+];
+
+/// the span of levels the meter shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum MeterScale {
+    /// -54db up to +12db, enough to follow a whole mix,
+    /// in the rev1 meter's steps
+    Wide,
+    /// -24db up to +12db, spread evenly for setting
+    /// levels
+    Narrow,
+}
+
+impl MeterScale {
+    /// the level the segment at `index` lights at, out of
+    /// `segments`
+    fn segment_db(self, index: usize, segments: usize) -> f32 {
+        let last = (segments.max(2) - 1) as f32;
+
+        match self {
+            // the rev1 table as it is at its own size, and
+            // followed between its steps at any other
+            MeterScale::Wide if segments == REV1.len() => REV1[index].0,
+            MeterScale::Wide => {
+                let position = index as f32 * (REV1.len() - 1) as f32 / last;
+                let below = (position as usize).min(REV1.len() - 2);
+                let fraction = position - below as f32;
+
+                REV1[below].0 + (REV1[below + 1].0 - REV1[below].0) * fraction
+            }
+            MeterScale::Narrow => -24.0 + 36.0 * index as f32 / last,
+        }
+    }
+}
+
+/// how long a peak is held before it drops
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Ballistics {
+    /// held as long as the rev1 table holds that level,
+    /// so overs stand out
+    Peak,
+    /// every peak held for the same short time
+    Even,
+}
+
+impl Ballistics {
+    fn peak_hold_ms(self, db: f32) -> u32 {
+        if self == Ballistics::Even {
+            return REV1[0].1;
+        }
+
+        // between the table's steps the hold is
+        // interpolated, beyond them it's the nearest one
+        match REV1.windows(2).find(|pair| db < pair[1].0) {
+            Some([(lower_db, lower), (upper_db, upper)]) => {
+                let fraction = ((db - lower_db) / (upper_db - lower_db)).clamp(0.0, 1.0);
+
+                lower + ((upper - lower) as f32 * fraction) as u32
+            }
+            _ => REV1[REV1.len() - 1].1,
+        }
+    }
+}
+
+/// how quickly the level bar falls back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum FallRate {
+    /// drops straight to the new level
+    Instant,
+    Fast,
+    Slow,
+}
+
+impl FallRate {
+    /// how long the level is held on each segment
+    fn hold_ms(self) -> u32 {
+        match self {
+            FallRate::Instant => 0,
+            FallRate::Fast => 25,
+            FallRate::Slow => 75,
+        }
+    }
+}
+
+/// where a segment lights, and how long a falling peak
+/// or level is held there before it drops
+#[derive(Debug, Clone, Copy)]
+pub struct ScaleStep {
+    pub input: f32,
+    pub peak_decay_ms: u32,
+    pub level_decay_ms: u32,
+}
+
+impl ScaleStep {
+    pub const fn new(input: f32, peak_decay_ms: u32, level_decay_ms: u32) -> Self {
+        Self {
+            input,
+            peak_decay_ms,
+            level_decay_ms,
+        }
+    }
+}
+
+/// maps meter input onto a channel's segments
+#[derive(Debug, Clone, Copy)]
+pub struct Scale<const SEGMENTS: usize> {
+    /// a step for each segment, from the bottom up
+    pub steps: [ScaleStep; SEGMENTS],
+    /// below the bottom segment, where nothing is lit
+    pub floor: ScaleStep,
+}
+
+impl<const SEGMENTS: usize> Scale<SEGMENTS> {
+    /// spread `scale` over the segments, with an input
+    /// at `reference_db` read as 0db
+    pub fn new(
+        scale: MeterScale,
+        reference_db: i8,
+        ballistics: Ballistics,
+        fall_rate: FallRate,
+    ) -> Self {
+        let step = |db: f32| {
+            ScaleStep::new(
+                db_to_input(db + reference_db as f32),
+                ballistics.peak_hold_ms(db),
+                fall_rate.hold_ms(),
+            )
+        };
+
+        Self {
+            steps: core::array::from_fn(|index| step(scale.segment_db(index, SEGMENTS))),
+            floor: ScaleStep::new(
+                DB_MINUS_INF,
+                ballistics.peak_hold_ms(scale.segment_db(0, SEGMENTS)),
+                fall_rate.hold_ms(),
+            ),
+        }
+    }
+
+    /// how many segments `raw` lights from the bottom up,
+    /// along with the step it reached
+    pub fn find(&self, raw: f32) -> (usize, ScaleStep) {
+        match self.steps.iter().rposition(|step| raw >= step.input) {
+            Some(index) => (index + 1, self.steps[index]),
+            None => (0, self.floor),
+        }
+    }
+
+    /// how far `raw` is between the step lighting `lit`
+    /// segments and the one above it, as a segment
+    /// intensity
+    pub fn partial(&self, lit: usize, raw: f32) -> u8 {
+        let upper = match self.steps.get(lit) {
+            Some(upper) => upper,
+            None => return 0,
+        };
+        let lower = match lit {
+            0 => self.floor,
+            _ => self.steps[lit - 1],
+        };
+        let fraction = ((raw - lower.input) / (upper.input - lower.input)).clamp(0.0, 1.0);
+
+        (fraction * MAX_INTENSITY as f32) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DB_MINUS_12, DB_MINUS_18, DB_MINUS_27, DB_MINUS_3, DB_MINUS_36, DB_MINUS_45, DB_MINUS_54,
+        DB_MINUS_6, DB_NOMINAL, DB_PLUS_12, DB_PLUS_3, DB_PLUS_6,
+    };
+
+    fn wide<const SEGMENTS: usize>() -> Scale<SEGMENTS> {
+        Scale::new(MeterScale::Wide, 0, Ballistics::Peak, FallRate::Instant)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn spans_the_range_at_every_size() {
+        fn check<const SEGMENTS: usize>() {
+            let scale = wide::<SEGMENTS>();
+
+            assert!(close(scale.steps[0].input, DB_MINUS_54), "{}", SEGMENTS);
+            assert!(
+                close(scale.steps[SEGMENTS - 1].input, DB_PLUS_12),
+                "{}",
+                SEGMENTS
+            );
+            assert!(
+                scale
+                    .steps
+                    .windows(2)
+                    .all(|pair| pair[1].input > pair[0].input),
+                "{}",
+                SEGMENTS
+            );
+        }
+
+        check::<8>();
+        check::<12>();
+        check::<20>();
+        check::<32>();
+    }
+
+    #[test]
+    fn twelve_segments_keep_the_rev1_table() {
+        let scale = wide::<12>();
+        let levels = [
+            DB_MINUS_54,
+            DB_MINUS_45,
+            DB_MINUS_36,
+            DB_MINUS_27,
+            DB_MINUS_18,
+            DB_MINUS_12,
+            DB_MINUS_6,
+            DB_MINUS_3,
+            DB_NOMINAL,
+            DB_PLUS_3,
+            DB_PLUS_6,
+            DB_PLUS_12,
+        ];
+        let holds = [300, 300, 300, 300, 300, 300, 300, 300, 600, 900, 1500, 2400];
+
+        for ((step, level), hold) in scale.steps.iter().zip(levels).zip(holds) {
+            assert!(close(step.input, level));
+            assert_eq!(step.peak_decay_ms, hold);
+        }
+
+        assert_eq!(scale.floor.peak_decay_ms, 300);
+    }
+
+    #[test]
+    fn other_sizes_follow_the_rev1_table() {
+        let scale = wide::<23>();
+
+        // every other segment lands on one of the table's
+        assert!(close(scale.steps[16].input, DB_NOMINAL));
+        assert!(close(scale.steps[17].input, db_to_input(1.5)));
+        assert_eq!(scale.steps[16].peak_decay_ms, 600);
+        assert_eq!(scale.steps[17].peak_decay_ms, 750);
+    }
+
+    #[test]
+    fn narrow_is_finer() {
+        let wide = wide::<12>();
+        let narrow = Scale::<12>::new(MeterScale::Narrow, 0, Ballistics::Peak, FallRate::Instant);
+
+        assert!(
+            narrow.steps[1].input - narrow.steps[0].input
+                < wide.steps[1].input - wide.steps[0].input
+        );
+        assert!(close(narrow.steps[11].input, DB_PLUS_12));
+    }
+
+    #[test]
+    fn reference_moves_every_step() {
+        let nominal = wide::<12>();
+        let hot = Scale::<12>::new(MeterScale::Wide, -6, Ballistics::Peak, FallRate::Instant);
+
+        for (nominal, hot) in nominal.steps.iter().zip(hot.steps) {
+            assert!(close(nominal.input - hot.input, 0.06));
+        }
+    }
+
+    #[test]
+    fn find_counts_the_segments_lit() {
+        fn check<const SEGMENTS: usize>() {
+            let scale = wide::<SEGMENTS>();
+
+            assert_eq!(scale.find(0.0).0, 0);
+            assert_eq!(scale.find(DB_MINUS_54).0, 1);
+            assert_eq!(scale.find(1.0).0, SEGMENTS);
+
+            for (index, step) in scale.steps.iter().enumerate() {
+                assert_eq!(scale.find(step.input + 1e-4).0, index + 1);
+            }
+        }
+
+        check::<8>();
+        check::<12>();
+        check::<20>();
+    }
+
+    #[test]
+    fn partial_is_how_far_to_the_next_segment() {
+        let scale = wide::<12>();
+        let lower = scale.steps[3].input;
+        let upper = scale.steps[4].input;
+
+        assert_eq!(scale.partial(4, lower), 0);
+        assert_eq!(scale.partial(4, (lower + upper) / 2.0), MAX_INTENSITY / 2);
+        assert_eq!(scale.partial(12, 1.0), 0);
+    }
+
+    #[test]
+    fn ballistics_and_fall_rate_set_the_holds() {
+        let peak = wide::<12>();
+        let even = Scale::<12>::new(MeterScale::Wide, 0, Ballistics::Even, FallRate::Slow);
+
+        assert_eq!(peak.steps[0].peak_decay_ms, 300);
+        assert_eq!(peak.steps[8].peak_decay_ms, 600);
+        assert_eq!(peak.steps[11].peak_decay_ms, 2400);
+        assert!(even.steps.iter().all(|step| step.peak_decay_ms == 300));
+        assert!(peak.steps.iter().all(|step| step.level_decay_ms == 0));
+        assert!(even.steps.iter().all(|step| step.level_decay_ms == 75));
+    }
+}
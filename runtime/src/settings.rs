@@ -0,0 +1,502 @@
+use crate::attenuator::VOLUME_UNITY;
+use crate::auto_brightness::{AutoBrightness, CURVE_POINTS};
+use crate::auto_dim::AutoDim;
+use crate::control::{AudioOutput, AUDIO_INPUTS};
+use crate::dsp::DSP_PRESETS;
+use crate::key::Keymap;
+use crate::scale::{Ballistics, FallRate, MeterScale, Scale, REFERENCE_DB_LIMIT};
+use crate::{EncoderTarget, TimeInstant, METER_SEGMENTS};
+
+/// how many bytes the saved settings take up, with a
+/// few to spare for settings still to come
+pub const SETTINGS_LEN: usize = 60;
+
+/// brightness presets, kept as shortcuts to the
+/// continuous brightness
+#[derive(Debug, Clone, Copy)]
+pub enum BrightnessLevel {
+    High,
+    Medium,
+    Low,
+}
+
+impl BrightnessLevel {
+    pub fn percent(self) -> u8 {
+        use BrightnessLevel::*;
+
+        match self {
+            High => 100,
+            Medium => 65,
+            Low => 30,
+        }
+    }
+
+    /// the next preset below `percent`, wrapping around
+    /// to high
+    pub fn below(percent: u8) -> Self {
+        use BrightnessLevel::*;
+
+        if percent > Medium.percent() {
+            Medium
+        } else if percent > Low.percent() {
+            Low
+        } else {
+            High
+        }
+    }
+}
+
+/// what's shown on the meter while booting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum BootSequence {
+    Off,
+    /// sweep the meter up and down
+    Sweep,
+    /// sweep, then test each segment
+    Full,
+}
+
+/// the settings each output keeps for itself, which
+/// are swapped in when switching over to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputPreset {
+    pub audio_volume: u8,
+    pub dsp_volume: u8,
+    pub brightness: u8,
+    pub auto_brightness: bool,
+    pub peaks: bool,
+    pub levels: bool,
+    /// the input level read as 0db
+    pub reference_db: i8,
+    pub meter_scale: MeterScale,
+    pub ballistics: Ballistics,
+    pub fall_rate: FallRate,
+}
+
+impl Default for OutputPreset {
+    fn default() -> Self {
+        Self {
+            audio_volume: VOLUME_UNITY,
+            dsp_volume: VOLUME_UNITY,
+            brightness: BrightnessLevel::High.percent(),
+            auto_brightness: false,
+            peaks: true,
+            levels: true,
+            reference_db: 0,
+            meter_scale: MeterScale::Wide,
+            ballistics: Ballistics::Peak,
+            fall_rate: FallRate::Instant,
+        }
+    }
+}
+
+/// the settings that carry over between states, such
+/// as into a fault and back out again
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub audio_output: AudioOutput,
+    pub audio_input: u8,
+    pub audio_mute: bool,
+    pub headphone_detect: bool,
+    pub audio_volume: u8,
+    pub mute_ramp_ms: u16,
+    /// the dsp preset for each output
+    pub dsp_presets: [u8; 2],
+    pub dsp_volume: u8,
+    /// the brightness picked by hand, which auto
+    /// brightness leaves alone to come back to
+    pub brightness: u8,
+    pub auto_brightness: bool,
+    pub brightness_curve: AutoBrightness,
+    pub auto_dim: AutoDim,
+    pub peaks: bool,
+    pub levels: bool,
+    pub reference_db: i8,
+    pub meter_scale: MeterScale,
+    pub ballistics: Ballistics,
+    pub fall_rate: FallRate,
+    pub encoder: EncoderTarget,
+    /// minutes without activity before going to standby,
+    /// zero never goes
+    pub auto_standby_minutes: u8,
+    pub keymap: Keymap,
+    pub boot_sequence: BootSequence,
+    /// how many times the watchdog has reset the unit,
+    /// kept alongside the settings
+    pub watchdog_resets: u16,
+    /// the presets for each output, the one for the
+    /// current output is only brought up to date when
+    /// switching away from it
+    pub output_presets: [OutputPreset; 2],
+}
+
+impl Settings {
+    /// the preset for the current output
+    pub fn output_preset(&self) -> OutputPreset {
+        OutputPreset {
+            audio_volume: self.audio_volume,
+            dsp_volume: self.dsp_volume,
+            brightness: self.brightness,
+            auto_brightness: self.auto_brightness,
+            peaks: self.peaks,
+            levels: self.levels,
+            reference_db: self.reference_db,
+            meter_scale: self.meter_scale,
+            ballistics: self.ballistics,
+            fall_rate: self.fall_rate,
+        }
+    }
+
+    /// the brightness the leds are shown at, following
+    /// the ambient light in auto
+    pub fn shown_brightness(&self) -> u8 {
+        if self.auto_brightness {
+            self.brightness_curve.output()
+        } else {
+            self.brightness
+        }
+    }
+
+    /// the scale the meter is shown in for the current
+    /// output
+    pub fn scale(&self) -> Scale<METER_SEGMENTS> {
+        Scale::new(
+            self.meter_scale,
+            self.reference_db,
+            self.ballistics,
+            self.fall_rate,
+        )
+    }
+
+    /// switch to `audio_output`, keeping the current
+    /// output's preset and applying the new one
+    pub fn switch_output(&mut self, audio_output: AudioOutput) {
+        if audio_output == self.audio_output {
+            return;
+        }
+
+        self.output_presets[self.audio_output as usize] = self.output_preset();
+        self.audio_output = audio_output;
+        self.apply(self.output_presets[audio_output as usize]);
+    }
+
+    /// pack the settings that are kept across a power
+    /// cycle, the mute isn't kept so the unit never
+    /// comes up silent without saying why
+    pub fn save(&self) -> [u8; SETTINGS_LEN] {
+        let mut bytes = [0; SETTINGS_LEN];
+        let len = self.pack(&mut bytes);
+
+        debug_assert!(len <= SETTINGS_LEN, "settings take {} bytes", len);
+
+        bytes
+    }
+
+    /// pack the settings into `bytes`, returns how many
+    /// bytes they take. any past the end of `bytes` are
+    /// cut off
+    fn pack(&self, bytes: &mut [u8]) -> usize {
+        let mut len = 0;
+        let mut writer = bytes.iter_mut();
+        let mut put = |values: &[u8]| {
+            len += values.len();
+
+            // the values go first, so the zip stops without
+            // taking a byte from the writer it won't fill
+            for (value, byte) in values.iter().zip(writer.by_ref()) {
+                *byte = *value;
+            }
+        };
+
+        put(&[
+            self.audio_output as u8,
+            self.audio_input,
+            self.headphone_detect as u8,
+        ]);
+        put(&self.mute_ramp_ms.to_le_bytes());
+        put(&self.dsp_presets);
+        put(&[self.auto_dim.timeout_minutes, self.auto_dim.floor]);
+        put(&[self.encoder as u8, self.boot_sequence as u8]);
+        put(&self.watchdog_resets.to_le_bytes());
+
+        for (lux, percent) in self.brightness_curve.curve {
+            put(&lux.to_le_bytes());
+            put(&[percent]);
+        }
+
+        for preset in [
+            self.output_preset(),
+            self.output_presets[1 - self.audio_output as usize],
+        ] {
+            put(&[
+                preset.audio_volume,
+                preset.dsp_volume,
+                preset.brightness,
+                preset.auto_brightness as u8,
+                preset.peaks as u8,
+                preset.levels as u8,
+                preset.reference_db as u8,
+                preset.meter_scale as u8,
+                preset.ballistics as u8,
+                preset.fall_rate as u8,
+            ]);
+        }
+
+        // kept after the presets, where older records
+        // have zeros for them to load as the defaults
+        put(&[self.auto_standby_minutes, self.keymap as u8]);
+
+        len
+    }
+
+    /// unpack settings packed by `save`, anything out of
+    /// range fails the whole lot
+    pub fn load(bytes: &[u8]) -> Option<Self> {
+        let mut reader = bytes.iter().copied();
+        let mut settings = Settings::default();
+
+        let output = |value| match value {
+            0 => Some(AudioOutput::Headphones),
+            1 => Some(AudioOutput::Speakers),
+            _ => None,
+        };
+        let flag = |value| match value {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        };
+        let mut take = || reader.next();
+
+        settings.audio_output = output(take()?)?;
+        settings.audio_input = Some(take()?).filter(|input| *input < AUDIO_INPUTS)?;
+        settings.headphone_detect = flag(take()?)?;
+        settings.mute_ramp_ms = u16::from_le_bytes([take()?, take()?]);
+
+        for preset in settings.dsp_presets.iter_mut() {
+            *preset = Some(take()?).filter(|preset| *preset < DSP_PRESETS)?;
+        }
+
+        settings.auto_dim.timeout_minutes = take()?;
+        settings.auto_dim.floor = take()?.min(100);
+        settings.encoder = match take()? {
+            0 => EncoderTarget::Volume,
+            1 => EncoderTarget::Brightness,
+            _ => return None,
+        };
+        settings.boot_sequence = match take()? {
+            0 => BootSequence::Off,
+            1 => BootSequence::Sweep,
+            2 => BootSequence::Full,
+            _ => return None,
+        };
+        settings.watchdog_resets = u16::from_le_bytes([take()?, take()?]);
+
+        for point in 0..CURVE_POINTS {
+            let lux = f32::from_le_bytes([take()?, take()?, take()?, take()?]);
+
+            settings.brightness_curve.set_point(point, lux, take()?);
+        }
+
+        let current = settings.audio_output as usize;
+
+        for index in [current, 1 - current] {
+            settings.output_presets[index] = OutputPreset {
+                audio_volume: take()?.min(VOLUME_UNITY),
+                dsp_volume: take()?.min(VOLUME_UNITY),
+                brightness: take()?.min(100),
+                auto_brightness: flag(take()?)?,
+                peaks: flag(take()?)?,
+                levels: flag(take()?)?,
+                reference_db: (take()? as i8).clamp(-REFERENCE_DB_LIMIT, REFERENCE_DB_LIMIT),
+                meter_scale: match take()? {
+                    0 => MeterScale::Wide,
+                    1 => MeterScale::Narrow,
+                    _ => return None,
+                },
+                ballistics: match take()? {
+                    0 => Ballistics::Peak,
+                    1 => Ballistics::Even,
+                    _ => return None,
+                },
+                fall_rate: match take()? {
+                    0 => FallRate::Instant,
+                    1 => FallRate::Fast,
+                    2 => FallRate::Slow,
+                    _ => return None,
+                },
+            };
+        }
+
+        settings.auto_standby_minutes = take()?;
+        settings.keymap = match take()? {
+            0 => Keymap::Standard,
+            1 => Keymap::Flipped,
+            _ => return None,
+        };
+        settings.apply(settings.output_presets[current]);
+
+        Some(settings)
+    }
+
+    fn apply(&mut self, preset: OutputPreset) {
+        self.audio_volume = preset.audio_volume;
+        self.dsp_volume = preset.dsp_volume;
+        self.brightness = preset.brightness;
+        self.auto_brightness = preset.auto_brightness;
+        self.peaks = preset.peaks;
+        self.levels = preset.levels;
+        self.reference_db = preset.reference_db;
+        self.meter_scale = preset.meter_scale;
+        self.ballistics = preset.ballistics;
+        self.fall_rate = preset.fall_rate;
+        self.brightness_curve.reset();
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let preset = OutputPreset::default();
+
+        Self {
+            audio_output: AudioOutput::Headphones,
+            audio_input: 0,
+            audio_mute: false,
+            headphone_detect: true,
+            audio_volume: preset.audio_volume,
+            mute_ramp_ms: 200,
+            dsp_presets: [0; 2],
+            dsp_volume: preset.dsp_volume,
+            brightness: preset.brightness,
+            auto_brightness: preset.auto_brightness,
+            brightness_curve: AutoBrightness::default(),
+            // restarted whenever the unit starts running
+            auto_dim: AutoDim::new(TimeInstant::from_ticks(0)),
+            peaks: preset.peaks,
+            levels: preset.levels,
+            reference_db: preset.reference_db,
+            meter_scale: preset.meter_scale,
+            ballistics: preset.ballistics,
+            fall_rate: preset.fall_rate,
+            encoder: EncoderTarget::Volume,
+            auto_standby_minutes: 0,
+            keymap: Keymap::Standard,
+            boot_sequence: BootSequence::Full,
+            watchdog_resets: 0,
+            output_presets: [preset; 2],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// settings with each output's preset changed from
+    /// the defaults, and from each other
+    fn changed() -> Settings {
+        let mut settings = Settings::default();
+
+        settings.audio_volume = 150;
+        settings.brightness = 40;
+        settings.reference_db = -6;
+        settings.meter_scale = MeterScale::Narrow;
+        settings.ballistics = Ballistics::Even;
+        settings.fall_rate = FallRate::Slow;
+        settings.switch_output(AudioOutput::Speakers);
+        settings.audio_volume = 90;
+        settings.auto_brightness = true;
+        settings.brightness = 70;
+        settings.reference_db = 4;
+        settings.fall_rate = FallRate::Fast;
+        settings
+    }
+
+    #[test]
+    fn presets_survive_a_save() {
+        let settings = changed();
+        let mut loaded = Settings::load(&settings.save()).unwrap();
+
+        assert_eq!(loaded.output_preset(), settings.output_preset());
+
+        loaded.switch_output(AudioOutput::Headphones);
+
+        assert_eq!(loaded.output_preset(), settings.output_presets[0]);
+        assert_eq!(loaded.meter_scale, MeterScale::Narrow);
+        assert_eq!(loaded.reference_db, -6);
+    }
+
+    #[test]
+    fn presets_carry_the_meter() {
+        let mut settings = changed();
+
+        assert_eq!(settings.meter_scale, MeterScale::Wide);
+        assert_eq!(settings.reference_db, 4);
+
+        settings.switch_output(AudioOutput::Headphones);
+
+        assert_eq!(settings.meter_scale, MeterScale::Narrow);
+        assert_eq!(settings.ballistics, Ballistics::Even);
+        assert_eq!(settings.fall_rate, FallRate::Slow);
+        assert_eq!(settings.reference_db, -6);
+    }
+
+    #[test]
+    fn manual_brightness_is_kept_in_auto() {
+        let mut settings = changed();
+
+        settings.brightness_curve.update(1.0);
+
+        let loaded = Settings::load(&settings.save()).unwrap();
+
+        assert!(loaded.auto_brightness);
+        assert_eq!(loaded.brightness, 70);
+        assert_eq!(settings.shown_brightness(), 5);
+    }
+
+    #[test]
+    fn out_of_range_fails_the_load() {
+        let settings = Settings::default();
+        let bytes = settings.save();
+
+        // the scale of the current output's preset
+        let mut bad = bytes;
+
+        bad[33 + 7] = 9;
+
+        assert!(Settings::load(&bad).is_none());
+
+        let mut far = bytes;
+
+        far[33 + 6] = 100;
+
+        assert_eq!(
+            Settings::load(&far).unwrap().reference_db,
+            REFERENCE_DB_LIMIT
+        );
+    }
+
+    #[test]
+    fn standby_and_keymap_survive_a_save() {
+        let mut settings = Settings::default();
+
+        settings.auto_standby_minutes = 30;
+        settings.keymap = Keymap::Flipped;
+
+        let loaded = Settings::load(&settings.save()).unwrap();
+
+        assert_eq!(loaded.auto_standby_minutes, 30);
+        assert_eq!(loaded.keymap, Keymap::Flipped);
+    }
+
+    #[test]
+    fn fits_the_record() {
+        let mut bytes = [0; SETTINGS_LEN];
+
+        assert!(Settings::default().pack(&mut bytes) <= SETTINGS_LEN);
+        // and it's the sizes that count, not the values
+        assert_eq!(
+            Settings::default().pack(&mut bytes),
+            Settings::default().pack(&mut [])
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use crate::hardware::board::{AmbientAdc, AmbientInput};
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::auto_brightness::LightSensor;
+use runtime::Message::*;
+use stm32f4xx_hal::adc::{config::SampleTime, Adc};
+
+/// lux per millivolt across the phototransistor load
+/// resistor, roughly 5mv per lux for a tept4400 into
+/// 10k
+const LUX_PER_MILLIVOLT: f32 = 0.2;
+
+/// a phototransistor and load resistor on an adc pin
+pub struct Phototransistor {
+    adc: Adc<AmbientAdc>,
+    input: AmbientInput,
+}
+
+impl Phototransistor {
+    pub fn new(adc: Adc<AmbientAdc>, input: AmbientInput) -> Self {
+        Self { adc, input }
+    }
+}
+
+impl LightSensor for Phototransistor {
+    fn lux(&mut self) -> Option<f32> {
+        let sample = self.adc.convert(&self.input, SampleTime::Cycles_480);
+        let millivolts = self.adc.sample_to_millivolts(sample);
+
+        Some(millivolts as f32 * LUX_PER_MILLIVOLT)
+    }
+}
+
+pub struct Ambient<Sensor> {
+    sensor: Sensor,
+}
+
+impl<Sensor> Ambient<Sensor>
+where
+    Sensor: LightSensor,
+{
+    pub fn new(sensor: Sensor) -> Self {
+        Self { sensor }
+    }
+
+    pub fn read(&mut self) {
+        if let Some(lux) = self.sensor.lux() {
+            AmbientUpdate(lux).send();
+        }
+    }
+}
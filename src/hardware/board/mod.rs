@@ -0,0 +1,58 @@
+// the board the firmware is built for, picked with one
+// of the `board-*` cargo features.
+//
+// a board module names the pins and peripherals each
+// part of the unit is wired to, puts the pins into the
+// mode the board needs, and says which level the
+// outputs and inputs are active at. the rest of the
+// firmware only ever goes through these names, so a new
+// board is a new module here and a feature to pick it.
+//
+// the second revision and the black pill prototype are
+// still to come, docs/boards.md has what's missing.
+
+/// the first revision of the main board, a stm32f411
+/// with the amplifier, dsp and meter all on one pcb
+#[cfg(any(
+    feature = "board-rev1",
+    feature = "board-rev1-f401",
+    feature = "board-surround"
+))]
+// the other boards take what they share from here and
+// name the rest themselves, leaving rev1's own versions
+// unused
+#[cfg_attr(not(feature = "board-rev1"), allow(dead_code))]
+mod rev1;
+
+#[cfg(feature = "board-rev1")]
+pub use rev1::*;
+
+/// rev1 built around a stm32f401, which lacks i2c2 data
+/// on pb9 so two of its pins are wired differently
+#[cfg(feature = "board-rev1-f401")]
+mod rev1_f401;
+
+#[cfg(feature = "board-rev1-f401")]
+pub use rev1_f401::*;
+
+/// rev1's parts on a stm32f411 in the 64 pin package,
+/// with a six channel meter read on port c
+#[cfg(feature = "board-surround")]
+mod surround;
+
+#[cfg(feature = "board-surround")]
+pub use surround::*;
+
+#[cfg(not(any(
+    feature = "board-rev1",
+    feature = "board-rev1-f401",
+    feature = "board-surround"
+)))]
+compile_error!("pick a board with one of the `board-*` features");
+
+#[cfg(any(
+    all(feature = "board-rev1", feature = "board-rev1-f401"),
+    all(feature = "board-rev1", feature = "board-surround"),
+    all(feature = "board-rev1-f401", feature = "board-surround")
+))]
+compile_error!("pick only one of the `board-*` features");
@@ -0,0 +1,208 @@
+#[cfg(feature = "board-rev1")]
+use stm32f4xx_hal::gpio::{gpioa, gpiob, gpioc};
+use stm32f4xx_hal::{
+    gpio::*,
+    pac::{ADC1, I2C2, SPI1, TIM10, USART2},
+    spi::NoMiso,
+};
+
+#[cfg(all(feature = "board-rev1", not(feature = "chip-stm32f411")))]
+compile_error!("rev1 carries a stm32f411, use `board-rev1-f401` for the f401 build");
+
+/// the mute line is high while the output is muted
+pub const MUTE_ACTIVE_HIGH: bool = true;
+/// the output relay is high while the speakers are on,
+/// and falls back to the headphones when let go
+pub const SPEAKER_RELAY_ACTIVE_HIGH: bool = true;
+/// the amplifier pulls its fault output low
+pub const FAULT_ACTIVE_HIGH: bool = false;
+/// the jack's detect switch is pulled low while a plug
+/// is in
+pub const HEADPHONE_DETECT_ACTIVE_HIGH: bool = false;
+
+/// the meter's size comes from the `meter-*` feature
+/// the board picks on the runtime, its chain of 595s
+/// drives the first channel nearest the far end
+pub use runtime::{METER_CHANNELS, METER_SEGMENTS};
+pub const METER_CHANNEL_NAMES: [&str; 2] = ["left", "right"];
+
+pub type DspBus = I2C2;
+pub type DspBusPins = (Pin<Input<Floating>, 'B', 10>, Pin<Input<Floating>, 'B', 9>);
+pub type VolumeBus = SPI1;
+pub type VolumeBusPins = (
+    Pin<Input<Floating>, 'A', 5>,
+    NoMiso,
+    Pin<Input<Floating>, 'A', 7>,
+);
+pub type BrightnessTimer = TIM10;
+pub type BrightnessPin = Pin<Alternate<PushPull, 3>, 'B', 8>;
+pub type RemoteSerial = USART2;
+pub type RemotePins = (Pin<Input<Floating>, 'A', 2>, Pin<Input<Floating>, 'A', 3>);
+pub type AmbientAdc = ADC1;
+
+pub type AudioOutputCtrl = Pin<Output<PushPull>, 'B', 13>;
+pub type AudioMuteCtrl = Pin<Output<PushPull>, 'B', 14>;
+pub type AudioInputSelect = (
+    Pin<Output<PushPull>, 'C', 13>,
+    Pin<Output<PushPull>, 'C', 14>,
+);
+pub type AudioVolumeCs = Pin<Output<PushPull>, 'A', 4>;
+pub type AmbientInput = Pin<Analog, 'A', 0>;
+pub type MeterInputClock = Pin<Input<PullUp>, 'A', 8>;
+/// a channel's 1-bit level from the meter's converter,
+/// sampled on each edge of the clock
+pub type MeterDataInput = EPin<Input<PullUp>>;
+pub type MeterDataOutput = Pin<Output<PushPull>, 'B', 5>;
+pub type MeterLatchOutput = Pin<Output<PushPull>, 'B', 6>;
+pub type MeterClockOutput = Pin<Output<PushPull>, 'B', 7>;
+/// a comparator on the led supply's current sense, high
+/// while any segment draws current
+pub type SegmentSense = Pin<Input<PullDown>, 'B', 12>;
+pub type EncoderInputA = Pin<Input<PullUp>, 'B', 0>;
+pub type EncoderInputB = Pin<Input<PullUp>, 'B', 1>;
+pub type EncoderSwitchInput = Pin<Input<PullUp>, 'A', 1>;
+/// the headphone jack's detect switch
+pub type HeadphoneDetectInput = Pin<Input<PullUp>, 'B', 15>;
+/// the amplifier's dc offset and fault output
+pub type FaultInput = Pin<Input<PullUp>, 'A', 9>;
+pub type KeyTriggerInput = Pin<Input<PullDown>, 'A', 12>;
+pub type KeyDataOutput = Pin<Output<PushPull>, 'B', 4>;
+pub type KeyLatchOutput = Pin<Output<PushPull>, 'B', 3>;
+pub type KeyClockOutput = Pin<Output<PushPull>, 'A', 15>;
+
+/// the peripherals the board's parts are wired to
+pub struct Peripherals {
+    pub dsp_bus: DspBus,
+    pub volume_bus: VolumeBus,
+    pub brightness_timer: BrightnessTimer,
+    pub remote_serial: RemoteSerial,
+    pub ambient_adc: AmbientAdc,
+}
+
+/// move the board's peripherals out of the device
+macro_rules! peripherals {
+    ($device:expr) => {
+        $crate::hardware::board::Peripherals {
+            dsp_bus: $device.I2C2,
+            volume_bus: $device.SPI1,
+            brightness_timer: $device.TIM10,
+            remote_serial: $device.USART2,
+            ambient_adc: $device.ADC1,
+        }
+    };
+}
+
+pub(crate) use peripherals;
+
+/// every pin the firmware uses, in the mode it's used
+/// in. the boards built on rev1 swap in their own types
+/// for the pins they wire differently
+pub struct Pins<DspPins = DspBusPins, KeyLatch = KeyLatchOutput> {
+    pub dsp_bus: DspPins,
+    pub audio_output_ctrl: AudioOutputCtrl,
+    pub audio_mute_ctrl: AudioMuteCtrl,
+    pub audio_input_select: AudioInputSelect,
+    pub volume_bus: VolumeBusPins,
+    pub audio_volume_cs: AudioVolumeCs,
+    pub brightness: BrightnessPin,
+    pub remote: RemotePins,
+    pub ambient: AmbientInput,
+    pub meter_clock: MeterInputClock,
+    pub meter_data_inputs: [MeterDataInput; METER_CHANNELS],
+    pub meter_data: MeterDataOutput,
+    pub meter_latch: MeterLatchOutput,
+    pub meter_register_clock: MeterClockOutput,
+    pub segment_sense: SegmentSense,
+    pub encoder_a: EncoderInputA,
+    pub encoder_b: EncoderInputB,
+    pub encoder_switch: EncoderSwitchInput,
+    pub headphone_detect: HeadphoneDetectInput,
+    pub fault: FaultInput,
+    pub key_trigger: KeyTriggerInput,
+    pub key_data: KeyDataOutput,
+    pub key_latch: KeyLatch,
+    pub key_clock: KeyClockOutput,
+}
+
+#[cfg(feature = "board-rev1")]
+impl Pins {
+    pub fn new(gpioa: gpioa::Parts, gpiob: gpiob::Parts, gpioc: gpioc::Parts) -> Self {
+        pins!(gpioa, gpiob, gpioc)
+    }
+}
+
+/// the value handed in, or rev1's when there isn't one
+macro_rules! either {
+    (, $rev1:expr) => {
+        $rev1
+    };
+    ($value:expr, $rev1:expr) => {
+        $value
+    };
+}
+
+/// rev1's pins, with any a board wires differently
+/// handed in. each pin is only moved out of its port
+/// where it's used, so rev1's pins that are replaced are
+/// left for the board to use elsewhere
+macro_rules! pins {
+    (
+        $gpioa:ident,
+        $gpiob:ident,
+        $gpioc:ident
+        $(, dsp_data: $dsp_data:expr)?
+        $(, key_latch: $key_latch:expr)?
+        $(, meter_data_inputs: $meter_data_inputs:expr)?
+        $(,)?
+    ) => {
+        $crate::hardware::board::rev1::Pins {
+            dsp_bus: (
+                $gpiob.pb10,
+                $crate::hardware::board::rev1::either!($($dsp_data)?, $gpiob.pb9),
+            ),
+            // the relays and mute come up let go
+            audio_output_ctrl: $gpiob.pb13.into_push_pull_output_in_state(
+                (!$crate::hardware::board::SPEAKER_RELAY_ACTIVE_HIGH).into(),
+            ),
+            audio_mute_ctrl: $gpiob
+                .pb14
+                .into_push_pull_output_in_state((!$crate::hardware::board::MUTE_ACTIVE_HIGH).into()),
+            audio_input_select: (
+                $gpioc.pc13.into_push_pull_output(),
+                $gpioc.pc14.into_push_pull_output(),
+            ),
+            volume_bus: ($gpioa.pa5, stm32f4xx_hal::spi::NoMiso {}, $gpioa.pa7),
+            audio_volume_cs: $gpioa.pa4.into_push_pull_output(),
+            brightness: $gpiob.pb8.into_alternate(),
+            remote: ($gpioa.pa2, $gpioa.pa3),
+            ambient: $gpioa.pa0.into_analog(),
+            meter_clock: $gpioa.pa8.into_pull_up_input(),
+            meter_data_inputs: $crate::hardware::board::rev1::either!(
+                $($meter_data_inputs)?,
+                [
+                    $gpioa.pa10.into_pull_up_input().erase(),
+                    $gpioa.pa11.into_pull_up_input().erase(),
+                ]
+            ),
+            meter_data: $gpiob.pb5.into_push_pull_output(),
+            meter_latch: $gpiob.pb6.into_push_pull_output(),
+            meter_register_clock: $gpiob.pb7.into_push_pull_output(),
+            segment_sense: $gpiob.pb12.into_pull_down_input(),
+            encoder_a: $gpiob.pb0.into_pull_up_input(),
+            encoder_b: $gpiob.pb1.into_pull_up_input(),
+            encoder_switch: $gpioa.pa1.into_pull_up_input(),
+            headphone_detect: $gpiob.pb15.into_pull_up_input(),
+            fault: $gpioa.pa9.into_pull_up_input(),
+            key_trigger: $gpioa.pa12.into_pull_down_input(),
+            key_data: $gpiob.pb4.into_push_pull_output(),
+            key_latch: $crate::hardware::board::rev1::either!(
+                $($key_latch)?,
+                $gpiob.pb3.into_push_pull_output()
+            ),
+            key_clock: $gpioa.pa15.into_push_pull_output(),
+        }
+    };
+}
+
+pub(crate) use either;
+pub(crate) use pins;
@@ -0,0 +1,29 @@
+use super::rev1;
+use stm32f4xx_hal::gpio::{gpioa, gpiob, gpioc, *};
+
+// everything but the dsp bus and the key latch is wired
+// as on rev1, so only those are named again here
+pub use super::rev1::*;
+
+#[cfg(not(feature = "chip-stm32f401"))]
+compile_error!("the rev1-f401 board carries a stm32f401, build it with `chip-stm32f401`");
+
+/// the f401 has no i2c2 data on pb9, so the dsp's data
+/// line moves to pb3 and the key latch takes pb9
+pub type DspBusPins = (Pin<Input<Floating>, 'B', 10>, Pin<Input<Floating>, 'B', 3>);
+pub type KeyLatchOutput = Pin<Output<PushPull>, 'B', 9>;
+
+/// rev1's pins with the dsp data and key latch swapped
+pub type Pins = rev1::Pins<DspBusPins, KeyLatchOutput>;
+
+impl Pins {
+    pub fn new(gpioa: gpioa::Parts, gpiob: gpiob::Parts, gpioc: gpioc::Parts) -> Self {
+        rev1::pins!(
+            gpioa,
+            gpiob,
+            gpioc,
+            dsp_data: gpiob.pb3.into_floating_input(),
+            key_latch: gpiob.pb9.into_push_pull_output(),
+        )
+    }
+}
@@ -0,0 +1,37 @@
+use super::rev1;
+use stm32f4xx_hal::gpio::{gpioa, gpiob, gpioc};
+
+// the same parts as rev1 on the same pins, only the
+// meter grows to six channels, which are named again
+pub use super::rev1::*;
+
+#[cfg(not(feature = "chip-stm32f411"))]
+compile_error!("the surround board carries a stm32f411, build it with `chip-stm32f411`");
+
+pub const METER_CHANNEL_NAMES: [&str; 6] = [
+    "front-left",
+    "front-right",
+    "centre",
+    "lfe",
+    "surround-left",
+    "surround-right",
+];
+
+impl Pins {
+    pub fn new(gpioa: gpioa::Parts, gpiob: gpiob::Parts, gpioc: gpioc::Parts) -> Self {
+        rev1::pins!(
+            gpioa,
+            gpiob,
+            gpioc,
+            // the 64 pin package's port c, in channel order
+            meter_data_inputs: [
+                gpioc.pc0.into_pull_up_input().erase(),
+                gpioc.pc1.into_pull_up_input().erase(),
+                gpioc.pc2.into_pull_up_input().erase(),
+                gpioc.pc3.into_pull_up_input().erase(),
+                gpioc.pc4.into_pull_up_input().erase(),
+                gpioc.pc5.into_pull_up_input().erase(),
+            ],
+        )
+    }
+}
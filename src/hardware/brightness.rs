@@ -1,46 +1,62 @@
-use crate::runtime::{State, State::*};
+use crate::hardware::board::BrightnessTimer;
+use crate::hardware::{time, TimeInstant};
 #[allow(unused_imports)]
 use rtt_target::*;
-use stm32f4xx_hal::{
-    pac::TIM10,
-    pwm::{PwmChannel, C1},
-};
-
-pub type BrightnessOutput = PwmChannel<TIM10, C1>;
-
-#[derive(Debug, Clone, Copy)]
-pub enum BrightnessLevel {
-    High,
-    Medium,
-    Low,
+use runtime::{elapsed, State, State::*};
+use stm32f4xx_hal::pwm::{PwmChannel, C1};
+
+pub type BrightnessOutput = PwmChannel<BrightnessTimer, C1>;
+
+/// how long a change in brightness takes to fade in
+const FADE_MS: u32 = 250;
+
+/// map a perceived lightness in percent to a duty
+/// cycle using the cie 1931 lightness curve
+fn duty(lightness: f32, max_duty: u16) -> u16 {
+    let luminance = if lightness > 8.0 {
+        let scaled = (lightness + 16.0) / 116.0;
+
+        scaled * scaled * scaled
+    } else {
+        lightness / 903.3
+    };
+
+    (luminance.clamp(0.0, 1.0) * max_duty as f32) as u16
 }
 
 pub struct Brightness {
     output: BrightnessOutput,
+    from: f32,
+    to: f32,
+    fade_start: Option<TimeInstant>,
 }
 
 impl Brightness {
     pub fn new(output: BrightnessOutput) -> Self {
-        Self { output }
+        Self {
+            output,
+            from: 0.0,
+            to: 0.0,
+            fade_start: None,
+        }
     }
 
     pub fn read(&mut self) {}
 
     pub fn write(&mut self, state: &State) {
-        use BrightnessLevel::*;
-
-        let max_duty = self.output.get_max_duty();
-
         match state {
-            Running { brightness, .. } => {
-                self.output.set_duty(
-                    max_duty
-                        / match brightness {
-                            High => 1,
-                            Medium => 2,
-                            Low => 6,
-                        },
-                );
+            Running { settings, .. } => {
+                self.fade_to(settings.auto_dim.brightness(settings.shown_brightness()) as f32);
+                self.output.enable();
+            }
+            // the sense input can only see segments lit at
+            // full brightness
+            SelfTest { .. } => {
+                self.fade_to(100.0);
+                self.output.enable();
+            }
+            Fault { settings, .. } | Menu { settings, .. } => {
+                self.fade_to(settings.shown_brightness() as f32);
                 self.output.enable();
             }
             _ => {
@@ -50,6 +66,32 @@ impl Brightness {
     }
 
     pub fn clock(&mut self) {
-        //
+        let max_duty = self.output.get_max_duty();
+        let lightness = self.current();
+
+        self.output.set_duty(duty(lightness, max_duty));
+    }
+
+    fn fade_to(&mut self, target: f32) {
+        if target != self.to {
+            self.from = self.current();
+            self.to = target;
+            self.fade_start = Some(time::now());
+        }
+    }
+
+    /// the lightness part way through the current fade
+    fn current(&mut self) -> f32 {
+        if let Some(fade_start) = self.fade_start {
+            let elapsed = elapsed(fade_start, time::now()).to_millis();
+
+            if elapsed < FADE_MS {
+                return self.from + (self.to - self.from) * elapsed as f32 / FADE_MS as f32;
+            }
+
+            self.fade_start = None;
+        }
+
+        self.to
     }
 }
@@ -0,0 +1,76 @@
+use crate::hardware::watchdog::ResetCause;
+use core::fmt::{self, Write};
+use heapless::String;
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::command::{self, Command};
+
+/// how often the down channel is checked for input
+pub const POLL_MS: u32 = 50;
+
+/// a line console on the rtt down channel, taking the
+/// same commands as the serial control interface and
+/// replying on the rtt terminal
+pub struct Console {
+    input: DownChannel,
+    line: String<64>,
+    reset_cause: ResetCause,
+}
+
+impl Console {
+    pub fn new(input: DownChannel, reset_cause: ResetCause) -> Self {
+        Self {
+            input,
+            line: String::new(),
+            reset_cause,
+        }
+    }
+
+    pub fn reset_cause(&self) -> ResetCause {
+        self.reset_cause
+    }
+
+    /// read what's come in, returning the command once a
+    /// full line has, the rest is left for the next read
+    pub fn read(&mut self) -> Option<Command> {
+        let mut byte = [0];
+
+        while self.input.read(&mut byte) == 1 {
+            let byte = byte[0];
+
+            if byte != b'\r' && byte != b'\n' {
+                if self.line.push(byte as char).is_err() {
+                    self.line.clear();
+                }
+
+                continue;
+            }
+
+            if self.line.is_empty() {
+                continue;
+            }
+
+            let command = command::parse(&self.line);
+
+            if command.is_none() {
+                rprintln!("unknown command: {}", self.line);
+            }
+
+            self.line.clear();
+
+            if command.is_some() {
+                return command;
+            }
+        }
+
+        None
+    }
+}
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        rprint!("{}", s);
+
+        Ok(())
+    }
+}
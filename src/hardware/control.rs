@@ -1,65 +1,44 @@
-use crate::runtime::{State, State::*};
+use crate::hardware::board::{
+    AudioInputSelect, AudioMuteCtrl, AudioOutputCtrl, AudioVolumeCs, DspBus, DspBusPins, VolumeBus,
+    VolumeBusPins, MUTE_ACTIVE_HIGH, SPEAKER_RELAY_ACTIVE_HIGH,
+};
 #[allow(unused_imports)]
 use rtt_target::*;
-use stm32f4xx_hal::gpio::*;
-
-#[derive(Clone, Copy, Debug)]
-pub enum AudioOutput {
-    Headphones,
-    Speakers,
-}
-
-pub type AudioOutputDsp = Pin<Output<PushPull>, 'B', 12>;
-pub type AudioOutputCtrl = Pin<Output<PushPull>, 'B', 13>;
-pub type AudioMuteCtrl = Pin<Output<PushPull>, 'B', 14>;
-
-pub struct Control {
-    audio_output_dsp: AudioOutputDsp,
-    audio_output_ctrl: AudioOutputCtrl,
-    audio_mute_ctrl: AudioMuteCtrl,
+use runtime::attenuator::Attenuator;
+use runtime::control::ControlPins;
+use runtime::dsp::SigmaDsp;
+use stm32f4xx_hal::{
+    i2c::I2c,
+    spi::{Spi, TransferModeNormal},
+};
+
+pub type AudioDspI2c = I2c<DspBus, DspBusPins>;
+pub type AudioDsp = SigmaDsp<AudioDspI2c>;
+pub type AudioVolumeSpi = Spi<VolumeBus, VolumeBusPins, TransferModeNormal>;
+pub type AudioVolume = Attenuator<AudioVolumeSpi, AudioVolumeCs>;
+pub type Control = runtime::control::Control<AudioPins, AudioVolumeSpi, AudioVolumeCs>;
+
+/// the board's relay, mute and mux lines
+pub struct AudioPins {
+    pub output_ctrl: AudioOutputCtrl,
+    pub mute_ctrl: AudioMuteCtrl,
+    pub input_select: AudioInputSelect,
 }
 
-impl Control {
-    pub fn new(
-        audio_output_dsp: AudioOutputDsp,
-        audio_output_ctrl: AudioOutputCtrl,
-        audio_mute_ctrl: AudioMuteCtrl,
-    ) -> Self {
-        Self {
-            audio_output_dsp,
-            audio_output_ctrl,
-            audio_mute_ctrl,
-        }
+impl ControlPins for AudioPins {
+    fn set_speakers(&mut self, speakers: bool) {
+        self.output_ctrl
+            .set_state((speakers == SPEAKER_RELAY_ACTIVE_HIGH).into());
     }
 
-    pub fn read(&mut self) {}
-
-    pub fn write(&mut self, state: &State) {
-        use AudioOutput::*;
-
-        if let Running { audio_output, .. } = state {
-            match audio_output {
-                Headphones => {
-                    self.audio_output_dsp.set_low();
-                    self.audio_output_ctrl.set_low();
-                }
-                Speakers => {
-                    self.audio_output_dsp.set_high();
-                    self.audio_output_ctrl.set_high();
-                }
-            }
-        }
-
-        if let Running { audio_mute, .. } = state {
-            if *audio_mute {
-                self.audio_mute_ctrl.set_high();
-            } else {
-                self.audio_mute_ctrl.set_low();
-            }
-        }
+    fn set_mute(&mut self, mute: bool) {
+        self.mute_ctrl.set_state((mute == MUTE_ACTIVE_HIGH).into());
     }
 
-    pub fn clock(&mut self) {
-        //
+    fn set_input(&mut self, input: u8) {
+        let (select_0, select_1) = &mut self.input_select;
+
+        select_0.set_state((input & 0b01 > 0).into());
+        select_1.set_state((input & 0b10 > 0).into());
     }
 }
@@ -0,0 +1,139 @@
+use core::fmt::{self, Write};
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::{exception, ExceptionFrame};
+#[allow(unused_imports)]
+use rtt_target::*;
+
+/// marks the log as written by us, rather than left
+/// over in ram from power up
+const MAGIC: u32 = 0x6372_7368;
+/// how much of a panic message is kept
+const MESSAGE_LEN: usize = 120;
+
+// the log is read back a word at a time
+const _: () = assert!(core::mem::size_of::<Crash>().is_multiple_of(4));
+
+/// what brought the unit down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum CrashKind {
+    Panic,
+    HardFault,
+}
+
+/// the last crash, kept in ram that isn't cleared on
+/// reset so it can be read back once the unit is up
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Crash {
+    magic: u32,
+    kind: u32,
+    pub pc: u32,
+    pub lr: u32,
+    pub cfsr: u32,
+    len: u32,
+    message: [u8; MESSAGE_LEN],
+}
+
+impl Crash {
+    pub fn kind(&self) -> CrashKind {
+        if self.kind == CrashKind::HardFault as u32 {
+            CrashKind::HardFault
+        } else {
+            CrashKind::Panic
+        }
+    }
+
+    /// the panic message and location, cut short if it
+    /// didn't fit
+    pub fn message(&self) -> &str {
+        let len = (self.len as usize).min(MESSAGE_LEN);
+
+        core::str::from_utf8(&self.message[..len]).unwrap_or("")
+    }
+}
+
+impl Write for Crash {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let len = self.len as usize;
+
+            if len + c.len_utf8() > MESSAGE_LEN {
+                break;
+            }
+
+            c.encode_utf8(&mut self.message[len..]);
+            self.len += c.len_utf8() as u32;
+        }
+
+        Ok(())
+    }
+}
+
+#[link_section = ".uninit.crash"]
+static mut CRASH: MaybeUninit<Crash> = MaybeUninit::uninit();
+
+/// the log's size in words
+const CRASH_WORDS: usize = core::mem::size_of::<Crash>() / 4;
+
+/// the crash logged before the last reset, if any
+pub fn last() -> Option<Crash> {
+    // the ram is whatever it came up as after a power
+    // cycle, so it's read a word at a time the way a
+    // peripheral would be, and nothing past the magic is
+    // looked at until it's matched
+    let words = addr_of!(CRASH).cast::<u32>();
+
+    if unsafe { words.read_volatile() } != MAGIC {
+        return None;
+    }
+
+    let mut crash = MaybeUninit::<Crash>::uninit();
+    let copy = crash.as_mut_ptr().cast::<u32>();
+
+    for index in 0..CRASH_WORDS {
+        unsafe { copy.add(index).write(words.add(index).read_volatile()) };
+    }
+
+    // every field is a plain integer and every word has
+    // been written, so any value is a valid log
+    let crash = unsafe { crash.assume_init() };
+
+    if crash.len as usize > MESSAGE_LEN || crash.kind > CrashKind::HardFault as u32 {
+        return None;
+    }
+
+    Some(crash)
+}
+
+pub fn clear() {
+    unsafe { addr_of_mut!(CRASH).cast::<u32>().write_volatile(0) };
+}
+
+/// log a crash, then reset back into normal operation
+pub fn record(kind: CrashKind, pc: u32, lr: u32, message: Option<&dyn fmt::Display>) -> ! {
+    let mut crash = Crash {
+        magic: MAGIC,
+        kind: kind as u32,
+        pc,
+        lr,
+        cfsr: unsafe { (*SCB::PTR).cfsr.read() },
+        len: 0,
+        message: [0; MESSAGE_LEN],
+    };
+
+    if let Some(message) = message {
+        write!(crash, "{}", message).ok();
+    }
+
+    unsafe { addr_of_mut!(CRASH).write(MaybeUninit::new(crash)) };
+
+    SCB::sys_reset()
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    record(CrashKind::HardFault, frame.pc(), frame.lr(), None)
+}
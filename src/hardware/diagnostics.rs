@@ -0,0 +1,133 @@
+use crate::hardware::{TimeDuration, TimeInstant};
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::elapsed;
+use runtime::queue::{self, QueueStats};
+
+/// how often the rates are worked out
+pub const SAMPLE_MS: u32 = 1000;
+
+/// edges on the meter clock handled this sample
+static METER_EDGES: AtomicU32 = AtomicU32::new(0);
+/// timer ticks idle spent asleep this sample
+static SLEPT: AtomicU32 = AtomicU32::new(0);
+/// the rates worked out over the last sample
+static METER_EDGE_RATE: AtomicU32 = AtomicU32::new(0);
+static CPU_LOAD: AtomicU32 = AtomicU32::new(0);
+/// the deepest a shift register's buffer has been
+static SHIFT_DEPTH: AtomicU32 = AtomicU32::new(0);
+/// keys seen again before they'd counted as let go
+static KEY_BOUNCES: AtomicU32 = AtomicU32::new(0);
+/// how often to print a summary, zero for never
+static SUMMARY_SECONDS: AtomicU32 = AtomicU32::new(0);
+
+pub fn meter_edge() {
+    METER_EDGES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn slept(duration: TimeDuration) {
+    SLEPT.fetch_add(duration.ticks(), Ordering::Relaxed);
+}
+
+pub fn shift_depth(depth: usize) {
+    SHIFT_DEPTH.fetch_max(depth as u32, Ordering::Relaxed);
+}
+
+pub fn key_bounce() {
+    KEY_BOUNCES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// print a summary every `seconds`, for soak testing
+pub fn set_summary(seconds: u32) {
+    SUMMARY_SECONDS.store(seconds, Ordering::Relaxed);
+}
+
+/// everything collected so far
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    pub meter_edge_rate: u32,
+    /// percent of the last sample spent awake
+    pub cpu_load: u32,
+    pub shift_depth: u32,
+    pub key_bounces: u32,
+    pub queue: QueueStats,
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        meter_edge_rate: METER_EDGE_RATE.load(Ordering::Relaxed),
+        cpu_load: CPU_LOAD.load(Ordering::Relaxed),
+        shift_depth: SHIFT_DEPTH.load(Ordering::Relaxed),
+        key_bounces: KEY_BOUNCES.load(Ordering::Relaxed),
+        queue: queue::stats(),
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "meter-edges={}/s cpu-load={}% shift-depth={} key-bounces={} \
+             queue-dropped={} queue-overflowed-input={} queue-coalesced={} \
+             queue-high-water={}/{}",
+            self.meter_edge_rate,
+            self.cpu_load,
+            self.shift_depth,
+            self.key_bounces,
+            self.queue.dropped,
+            self.queue.overflowed_input,
+            self.queue.coalesced,
+            self.queue.input_high_water,
+            self.queue.other_high_water,
+        )
+    }
+}
+
+/// works out the rates at the end of each sample
+pub struct Diagnostics {
+    sample_start: TimeInstant,
+    summary_start: TimeInstant,
+}
+
+impl Diagnostics {
+    pub fn new(now: TimeInstant) -> Self {
+        Self {
+            sample_start: now,
+            summary_start: now,
+        }
+    }
+
+    /// finish the current sample, returns true when a
+    /// summary is due
+    pub fn sample(&mut self, now: TimeInstant) -> bool {
+        let sample = elapsed(self.sample_start, now);
+
+        self.sample_start = now;
+
+        if sample.ticks() > 0 {
+            let edges = METER_EDGES.swap(0, Ordering::Relaxed) as u64;
+            let slept = SLEPT.swap(0, Ordering::Relaxed).min(sample.ticks()) as u64;
+
+            METER_EDGE_RATE.store(
+                (edges * 1000 / sample.to_millis().max(1) as u64) as u32,
+                Ordering::Relaxed,
+            );
+            CPU_LOAD.store(
+                (100 - slept * 100 / sample.ticks() as u64) as u32,
+                Ordering::Relaxed,
+            );
+        }
+
+        let seconds = SUMMARY_SECONDS.load(Ordering::Relaxed);
+
+        if seconds > 0 && elapsed(self.summary_start, now).to_secs() >= seconds {
+            self.summary_start = now;
+
+            return true;
+        }
+
+        false
+    }
+}
@@ -0,0 +1,41 @@
+use crate::hardware::board::{EncoderInputA, EncoderInputB, EncoderSwitchInput};
+use crate::hardware::debounce::*;
+use fugit::ExtU32;
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::encoder::QuadratureDecoder;
+use runtime::Message::*;
+
+pub struct Encoder {
+    a: EncoderInputA,
+    b: EncoderInputB,
+    switch: EncoderSwitchInput,
+    decoder: QuadratureDecoder,
+    debouncer: Debouncer<1, ()>,
+}
+
+impl Encoder {
+    pub fn new(a: EncoderInputA, b: EncoderInputB, switch: EncoderSwitchInput) -> Self {
+        Self {
+            a,
+            b,
+            switch,
+            decoder: QuadratureDecoder::new(),
+            debouncer: Debouncer::new(),
+        }
+    }
+
+    pub fn read(&mut self) {
+        if let Some(direction) = self.decoder.update(self.a.is_high(), self.b.is_high()) {
+            EncoderTurn(direction).send();
+        }
+
+        if self.switch.is_low() {
+            if self.debouncer.is_ok(()) {
+                EncoderPush.send();
+            }
+
+            self.debouncer.update((), 70.millis());
+        }
+    }
+}
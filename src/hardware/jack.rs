@@ -0,0 +1,52 @@
+use crate::hardware::board::{HeadphoneDetectInput, HEADPHONE_DETECT_ACTIVE_HIGH};
+use crate::hardware::debounce::*;
+use fugit::ExtU32;
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::Message::*;
+
+/// how long the detect switch has to settle on a level
+/// before it's believed
+const SETTLE_MS: u32 = 50;
+
+pub struct HeadphoneJack {
+    detect: HeadphoneDetectInput,
+    /// the level last sent, none until the first one has
+    /// settled
+    inserted: Option<bool>,
+    debouncer: Debouncer<2, bool>,
+}
+
+impl HeadphoneJack {
+    pub fn new(detect: HeadphoneDetectInput) -> Self {
+        Self {
+            detect,
+            inserted: None,
+            debouncer: Debouncer::new(),
+        }
+    }
+
+    pub fn read(&mut self) {
+        let inserted = self.detect.is_high() == HEADPHONE_DETECT_ACTIVE_HIGH;
+
+        // nothing's been sent yet, so the level it starts
+        // on has to settle like any change, then it's sent
+        // once whichever it is, as a saved headphone output
+        // needs telling when nothing is plugged in
+        if self.inserted.is_none() && self.debouncer.is_empty() {
+            self.debouncer.update(true, SETTLE_MS.millis());
+            self.debouncer.update(false, SETTLE_MS.millis());
+        }
+
+        // every sample holds off the other level, so a
+        // change only goes through once the switch has
+        // stopped bouncing
+        self.debouncer.update(!inserted, SETTLE_MS.millis());
+
+        if self.inserted != Some(inserted) && self.debouncer.is_ok(inserted) {
+            self.inserted = Some(inserted);
+
+            HeadphonesInserted(inserted).send();
+        }
+    }
+}
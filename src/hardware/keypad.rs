@@ -1,35 +1,66 @@
+use crate::hardware::board::{KeyClockOutput, KeyDataOutput, KeyLatchOutput, KeyTriggerInput};
 use crate::hardware::debounce::*;
+use crate::hardware::diagnostics;
 use crate::hardware::shift::*;
-use crate::runtime::Message::*;
 use fugit::ExtU32;
+use heapless::LinearMap;
 #[allow(unused_imports)]
 use rtt_target::*;
-use stm32f4xx_hal::gpio::*;
+use runtime::key::{Key, Keymap};
+use runtime::{Message::*, State, State::*};
 
 pub enum AudioOutput {
     Headphones,
     Speakers,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum Key {
-    Unassigned(usize),
-    ToggleBrightness,
-    TogglePeaks,
-    ToggleLevels,
-    ToggleOutput,
-    ToggleMute,
-}
+/// the pattern each row is driven with, the top row
+/// first
+const ROWS: [u8; 8] = [
+    0b1000_0000,
+    0b0100_0000,
+    0b0010_0000,
+    0b0001_0000,
+    0b0000_1000,
+    0b0000_0100,
+    0b0000_0010,
+    0b0000_0001,
+];
+
+/// how long a key is held before it starts repeating
+const REPEAT_DELAY_MS: u32 = 400;
+/// how often a held key repeats
+const REPEAT_RATE_MS: u32 = 80;
+/// how long a key is held before it counts as held
+const HOLD_DELAY_MS: u32 = 800;
+/// how long a key has to go unseen to count as let go
+const RELEASE_MS: u32 = 70;
 
-pub type KeyTriggerInput = Pin<Input<PullDown>, 'A', 12>;
-pub type KeyDataOutput = Pin<Output<PushPull>, 'B', 4>;
-pub type KeyLatchOutput = Pin<Output<PushPull>, 'B', 3>;
-pub type KeyClockOutput = Pin<Output<PushPull>, 'A', 15>;
+/// the rows driven by `pattern`, the lowest bit first
+fn bits(pattern: u8) -> impl Iterator<Item = bool> {
+    (0..u8::BITS).map(move |index| pattern & 1 << index > 0)
+}
 
-pub type KeyRegister = ShiftRegister<8, Key, KeyDataOutput, KeyLatchOutput, KeyClockOutput>;
+pub type KeyRegister = ShiftRegister<Key, KeyDataOutput, KeyLatchOutput, KeyClockOutput>;
 
 pub struct Keypad {
     debouncer: Debouncer<8, Key>,
+    repeater: Debouncer<8, Key>,
+    /// keys that hold, still down, and whether the hold
+    /// has been sent yet
+    held: LinearMap<Key, bool, 8>,
+    /// whether each key was down on the last scan
+    down: LinearMap<Key, bool, 8>,
+    /// scanning every row at once, for waking up
+    standby: bool,
+    /// the key on each row
+    keys: [Key; 8],
+    /// no key has been seen down since going to standby,
+    /// so the key held to get there doesn't wake it again
+    released: bool,
+    /// every row has been latched and no key is down, so
+    /// a press will raise the trigger
+    armed: bool,
     trigger: KeyTriggerInput,
     register: KeyRegister,
 }
@@ -38,34 +69,108 @@ impl Keypad {
     pub fn new(trigger: KeyTriggerInput, register: KeyRegister) -> Self {
         Self {
             debouncer: Debouncer::new(),
+            repeater: Debouncer::new(),
+            held: LinearMap::new(),
+            down: LinearMap::new(),
+            standby: false,
+            keys: Keymap::Standard.keys(),
+            released: false,
+            armed: false,
             trigger,
             register,
         }
     }
 
     pub fn read(&mut self) {
-        use Key::*;
-
-        self.register.write(ToggleMute, 0b1000_0000);
-        self.register.write(ToggleOutput, 0b0100_0000);
-        self.register.write(Unassigned(3), 0b0010_0000);
-        self.register.write(Unassigned(4), 0b0001_0000);
-        self.register.write(ToggleBrightness, 0b0000_1000);
-        self.register.write(TogglePeaks, 0b0000_0100);
-        self.register.write(ToggleLevels, 0b0000_0010);
-        self.register.write(Unassigned(8), 0b0000_0001);
+        if self.standby {
+            self.register.write(Key::Wake, bits(0b1111_1111));
+
+            return;
+        }
+
+        for (key, row) in self.keys.into_iter().zip(ROWS) {
+            self.register.write(key, bits(row));
+        }
+    }
+
+    pub fn write(&mut self, state: &State) {
+        let standby = matches!(state, Standby { .. });
+
+        if standby && !self.standby {
+            self.released = false;
+        }
+
+        self.standby = standby;
+        self.keys = state.settings().keymap.keys();
+        self.armed &= self.standby;
+    }
+
+    /// whether the trigger will wake the core from a stop
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    /// the rows can't be trusted after a stop, so have
+    /// them latched again before the next one
+    pub fn disarm(&mut self) {
+        self.armed = false;
     }
 
     pub fn clock(&mut self) {
         let trigger = &mut self.trigger;
 
         if let ShiftState::LatchOff(id, _) = self.register.clock() {
-            if trigger.is_high() {
+            if id == Key::Wake {
+                if trigger.is_low() {
+                    self.released = true;
+                } else if self.released {
+                    // the key that woke the unit only counts
+                    // again once it's been let go
+                    for key in self.keys {
+                        self.debouncer.update(key, RELEASE_MS.millis());
+                    }
+
+                    KeypadUpdate(Key::Wake).send();
+                }
+
+                self.armed = self.standby && trigger.is_low();
+            } else if trigger.is_high() {
+                let was_down = self.down.insert(id, true).ok().flatten() == Some(true);
+
+                // back again before it counted as let go
+                if !was_down && !self.debouncer.is_ok(id) {
+                    diagnostics::key_bounce();
+                }
+
                 if self.debouncer.is_ok(id) {
+                    if id.holds() {
+                        self.held.insert(id, false).ok();
+                        self.repeater.update(id, HOLD_DELAY_MS.millis());
+                    } else {
+                        KeypadUpdate(id).send();
+
+                        self.repeater.update(id, REPEAT_DELAY_MS.millis());
+                    }
+                } else if id.holds() {
+                    if self.held.get(&id) == Some(&false) && self.repeater.is_ok(id) {
+                        KeypadHold(id).send();
+
+                        self.held.insert(id, true).ok();
+                    }
+                } else if id.repeats() && self.repeater.is_ok(id) {
                     KeypadUpdate(id).send();
+
+                    self.repeater.update(id, REPEAT_RATE_MS.millis());
                 }
 
-                self.debouncer.update(id, 70.millis());
+                self.debouncer.update(id, RELEASE_MS.millis());
+            } else {
+                self.down.insert(id, false).ok();
+
+                // let go before it counted as held
+                if id.holds() && self.debouncer.is_ok(id) && self.held.remove(&id) == Some(false) {
+                    KeypadUpdate(id).send();
+                }
             }
         }
     }
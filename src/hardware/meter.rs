@@ -1,14 +1,21 @@
+use crate::hardware::board::{
+    MeterClockOutput, MeterDataInput, MeterDataOutput, MeterInputClock, MeterLatchOutput,
+    SegmentSense, METER_CHANNELS, METER_SEGMENTS,
+};
+use crate::hardware::diagnostics;
+use crate::hardware::self_test::*;
 use crate::hardware::shift::*;
 use crate::hardware::time;
-use crate::hardware::TimeInstant;
-use crate::runtime::{Message::*, State, State::*};
+use crate::hardware::{TimeInstant, CLOCK_HZ};
 #[allow(unused_imports)]
 use rtt_target::*;
+use runtime::meter::{chain, fault, flash, intensities, segments, ChannelMask};
+use runtime::modulation::{edges_per_tick, Modulator};
+use runtime::{elapsed, Message::*, State, State::*};
 use stm32f4xx_hal::gpio::*;
 
 /// the number of rising and falling edges on the
-/// clock pin that can occur per the left and right
-/// input period
+/// clock pin that can occur per input period
 const CLOCKS_PER_INPUT: u32 = 96;
 /// how many clocks to read before sending the read
 /// average to state.
@@ -19,153 +26,157 @@ const CLOCKS_PER_INPUT: u32 = 96;
 /// using 96 * 16 results in a 30ms delay when the
 /// clock is running at 24khz.
 const CLOCKS_PER_READ: u32 = CLOCKS_PER_INPUT * 16;
+/// how long the input number is shown after switching
+const INPUT_FLASH_MS: u32 = 1000;
 
-trait MeterStateExt {
-    fn levels(&self) -> (usize, usize);
-}
-
-impl MeterStateExt for &State {
-    fn levels(&self) -> (usize, usize) {
-        let mut left_result = 0;
-        let mut right_result = 0;
-
-        if let Running {
-            left,
-            right,
-            peaks,
-            levels,
-            ..
-        } = self
-        {
-            if *levels {
-                left_result |= left.level;
-                right_result |= right.level;
-            }
-
-            if *peaks {
-                left_result |= left.peak;
-                right_result |= right.peak;
-            }
-        }
+pub type MeterRegister = ShiftRegister<(), MeterDataOutput, MeterLatchOutput, MeterClockOutput>;
 
-        (left_result, right_result)
-    }
-}
-
-pub type MeterRegister = ShiftRegister<
-    24,
-    (),
-    Pin<Output<PushPull>, 'B', 5>,
-    Pin<Output<PushPull>, 'B', 6>,
-    Pin<Output<PushPull>, 'B', 7>,
->;
-
-pub type MeterInputClock = Pin<Input<PullUp>, 'A', 8>;
-pub type MeterInputLeft = Pin<Input<PullUp>, 'A', 10>;
-pub type MeterInputRight = Pin<Input<PullUp>, 'A', 11>;
-
-pub struct MeterInput {
+/// the meter converter's shared clock and a data pin
+/// for each channel
+pub struct MeterInput<const CHANNELS: usize> {
     clock: MeterInputClock,
-    left: MeterInputLeft,
-    right: MeterInputRight,
+    data: [MeterDataInput; CHANNELS],
     clock_count: u32,
-    left_count: u32,
-    right_count: u32,
+    counts: [u32; CHANNELS],
 }
 
-impl MeterInput {
-    pub fn new(clock: MeterInputClock, left: MeterInputLeft, right: MeterInputRight) -> Self {
+impl<const CHANNELS: usize> MeterInput<CHANNELS> {
+    pub fn new(clock: MeterInputClock, data: [MeterDataInput; CHANNELS]) -> Self {
         Self {
             clock_count: 0,
-            left_count: 0,
-            right_count: 0,
+            counts: [0; CHANNELS],
             clock,
-            left,
-            right,
+            data,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct MeterChannel {
-    pub level: usize,
-    pub level_decay: TimeInstant,
-    pub peak: usize,
-    pub peak_decay: TimeInstant,
-    pub calculated: usize,
+/// the meter's segments, driven through a chain of
+/// shift registers
+pub struct Meter<const SEGMENTS: usize, const CHANNELS: usize> {
+    input: MeterInput<CHANNELS>,
+    modulator: Modulator<SEGMENTS, CHANNELS>,
+    audio_input: Option<u8>,
+    flash_start: Option<TimeInstant>,
+    self_test: SelfTestSequence<SEGMENTS, CHANNELS>,
+    pub register: MeterRegister,
 }
 
-impl Default for MeterChannel {
-    fn default() -> Self {
+impl<const SEGMENTS: usize, const CHANNELS: usize> Meter<SEGMENTS, CHANNELS> {
+    /// each channel's mask needs a bit per segment, and
+    /// the menu two bars
+    const FITS: () = assert!(SEGMENTS <= ChannelMask::BITS as usize && CHANNELS >= 2);
+
+    /// shifting one edge a tick leaves longer chains
+    /// flickering, so each tick shifts as many as the
+    /// chain needs to keep the refresh rate up
+    const EDGES_PER_TICK: u32 = edges_per_tick(SEGMENTS * CHANNELS, CLOCK_HZ);
+
+    pub fn new(input: MeterInput<CHANNELS>, register: MeterRegister, sense: SegmentSense) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::FITS;
+
         Self {
-            calculated: 0,
-            level: 0,
-            level_decay: time::now(),
-            peak: 0,
-            peak_decay: time::now(),
+            input,
+            modulator: Modulator::new(),
+            audio_input: None,
+            flash_start: None,
+            self_test: SelfTestSequence::new(sense),
+            register,
         }
     }
 }
 
-pub struct Meter {
-    input: MeterInput,
-    pub register: MeterRegister,
-}
-
-impl Meter {
-    pub fn new(input: MeterInput, register: MeterRegister) -> Self {
-        Self { input, register }
-    }
-
+// the meter sends and draws messages in the runtime's
+// own size, so it's only read and written at that size
+impl Meter<METER_SEGMENTS, METER_CHANNELS> {
     pub fn read(&mut self) {
         let MeterInput {
             clock_count,
             clock,
-            left,
-            left_count,
-            right,
-            right_count,
+            data,
+            counts,
         } = &mut self.input;
 
         clock.clear_interrupt_pending_bit();
+        diagnostics::meter_edge();
 
         if *clock_count == CLOCKS_PER_READ {
-            MeterUpdate(
-                *left_count as f32 / CLOCKS_PER_READ as f32,
-                *right_count as f32 / CLOCKS_PER_READ as f32,
-            )
-            .send();
+            let mut levels = [0.0; METER_CHANNELS];
 
-            *clock_count = 0;
-            *left_count = 0;
-            *right_count = 0;
-        }
+            for (level, count) in levels.iter_mut().zip(counts.iter_mut()) {
+                *level = *count as f32 / CLOCKS_PER_READ as f32;
+                *count = 0;
+            }
 
-        if left.is_high() {
-            *left_count += 1;
+            MeterUpdate(levels).send();
+
+            *clock_count = 0;
         }
 
-        if right.is_high() {
-            *right_count += 1;
+        for (data, count) in data.iter().zip(counts.iter_mut()) {
+            if data.is_high() {
+                *count += 1;
+            }
         }
 
         *clock_count += 1;
     }
 
     pub fn write(&mut self, state: &State) {
-        let mut shift_data = 0;
-        let (left, right) = state.levels();
+        if let SelfTest { sequence, .. } = state {
+            if self.self_test.is_idle() {
+                self.self_test.start(*sequence, time::now());
+            }
+
+            return;
+        }
+
+        self.self_test.reset();
+
+        if let Fault { .. } = state {
+            self.modulator.set(fault());
+
+            return;
+        }
+
+        if let Running { settings, .. } = state {
+            let audio_input = settings.audio_input;
 
-        shift_data <<= 12;
-        shift_data |= left.reverse_bits().rotate_left(12);
+            // flash the input number when it changes, but
+            // not when it's first set
+            if self.audio_input.is_some() && self.audio_input != Some(audio_input) {
+                self.flash_start = Some(time::now());
+                self.modulator.set(flash(audio_input));
+            }
 
-        shift_data <<= 12;
-        shift_data |= right.reverse_bits().rotate_left(12);
+            self.audio_input = Some(audio_input);
+        }
 
-        self.register.write((), shift_data);
+        if self.flash_start.is_none() {
+            self.modulator.set(intensities(state));
+        }
     }
 
     pub fn clock(&mut self) {
-        self.register.clock();
+        // segments go straight off during the self test so
+        // the sense input only sees the one being tested
+        if let Some(lit) = self.self_test.clock(time::now()) {
+            self.modulator.show(segments(&lit));
+        }
+
+        if let Some(flash_start) = self.flash_start {
+            if elapsed(flash_start, time::now()).to_millis() >= INPUT_FLASH_MS {
+                self.flash_start = None;
+            }
+        }
+
+        for _ in 0..Self::EDGES_PER_TICK {
+            if self.register.is_empty() {
+                self.register.write((), chain(&self.modulator.next_frame()));
+            }
+
+            self.register.clock();
+        }
     }
 }
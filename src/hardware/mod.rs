@@ -1,116 +1,324 @@
+pub mod ambient;
+pub mod board;
 pub mod brightness;
+pub mod console;
 pub mod control;
+pub mod crash;
 pub mod debounce;
+pub mod diagnostics;
+pub mod encoder;
+pub mod jack;
 pub mod keypad;
 pub mod meter;
 pub mod monotonic;
+pub mod power;
+pub mod protection;
+pub mod remote;
+pub mod report;
+pub mod self_test;
 pub mod shift;
+pub mod storage;
+pub mod watchdog;
+
+#[cfg(not(any(feature = "chip-stm32f411", feature = "chip-stm32f401")))]
+compile_error!("pick a chip with one of the `chip-*` features");
+#[cfg(all(feature = "chip-stm32f411", feature = "chip-stm32f401"))]
+compile_error!("only one of the `chip-*` features can be picked");
 
 pub use crate::hardware::inner::monotonics as time;
-pub use crate::hardware::inner::TimeDuration;
-pub use crate::hardware::inner::TimeInstant;
+pub use runtime::{TimeDuration, TimeInstant};
+
+/// the rate the clock task runs at, the pins it drives
+/// move at most this often
+pub const CLOCK_HZ: u32 = 20_000;
 
+use crate::hardware::ambient::*;
+use crate::hardware::board::{Pins, METER_CHANNELS, METER_SEGMENTS};
 use crate::hardware::brightness::*;
+use crate::hardware::console::*;
 use crate::hardware::control::*;
+use crate::hardware::diagnostics::*;
+use crate::hardware::encoder::*;
+use crate::hardware::jack::*;
 use crate::hardware::keypad::*;
 use crate::hardware::meter::*;
 use crate::hardware::monotonic::*;
+use crate::hardware::power::*;
+use crate::hardware::protection::*;
+use crate::hardware::remote::*;
+use crate::hardware::report::*;
 use crate::hardware::shift::*;
-use crate::runtime::{Message::*, State, Q};
-use fugit::{Duration, ExtU32, Instant};
+use crate::hardware::storage::*;
+use crate::hardware::watchdog::*;
+use crate::log::{self, info, warn, Event};
+use defmt::Display2Format;
+use fugit::ExtU32;
+use rtic::Mutex;
 use rtt_target::*;
-use stm32f4xx_hal::{gpio::*, pac, prelude::*, timer::Timer};
+use runtime::command::Command;
+use runtime::{queue, Message::*, State, TICK_MS, TIMER_HZ};
+use stm32f4xx_hal::{
+    adc::{config::AdcConfig, Adc},
+    gpio::*,
+    i2c::I2c,
+    pac,
+    prelude::*,
+    serial::Serial,
+    spi::{Mode, Phase, Polarity, Spi},
+    timer::Timer,
+};
+
+/// carry out a command from the serial port or the rtt
+/// console, replying on `out`
+fn run(
+    command: Command,
+    out: &mut impl Report,
+    reset_cause: ResetCause,
+    state: &mut impl Mutex<T = State>,
+    control: &mut impl Mutex<T = Control>,
+) {
+    match command {
+        Command::Send(msg) => msg.send(),
+        Command::Status => state.lock(|state| out.status(state)),
+        Command::Meter => state.lock(|state| out.meter(state)),
+        Command::Crash => out.crash(crash::last()),
+        Command::ClearCrash => {
+            crash::clear();
+            out.crash(None);
+        }
+        Command::QueueStatus => out.queue_status(queue::stats()),
+        Command::Diagnostics => out.diagnostics(snapshot()),
+        Command::Summary(seconds) => set_summary(seconds),
+        Command::LogLevel(level) => log::set_level(level),
+        Command::LogMirror(mirror) => log::set_mirror(mirror),
+        Command::ResetCause => state.lock(|state| out.reset_status(reset_cause, state)),
+        Command::DspStatus => control.lock(|control| out.dsp_status(control.dsp_status())),
+    }
+}
 
-#[rtic::app(device = stm32f4xx_hal::pac, dispatchers = [SPI1, SPI2, SPI3])]
+#[rtic::app(device = stm32f4xx_hal::pac, dispatchers = [SPI2, SPI3, SPI4])]
 mod inner {
     use super::*;
 
     #[monotonic(binds = TIM2, default = true)]
-    type MonotonicTimer = MonoTimer<pac::TIM2, 8_000_000>;
-    pub type TimeInstant = Instant<u32, 1, 8_000_000>;
-    pub type TimeDuration = Duration<u32, 1, 8_000_000>;
+    type MonotonicTimer = MonoTimer<pac::TIM2, TIMER_HZ>;
 
     #[shared]
     struct Shared {
         brightness: Brightness,
         control: Control,
+        encoder: Encoder,
+        jack: HeadphoneJack,
         keypad: Keypad,
-        meter: Meter,
+        meter: Meter<METER_SEGMENTS, METER_CHANNELS>,
+        protection: Protection,
+        remote: Remote,
         state: State,
     }
 
     #[local]
-    struct Local {}
+    struct Local {
+        ambient: Ambient<Phototransistor>,
+        audio_dsp: AudioDsp,
+        console: Console,
+        diagnostics: Diagnostics,
+        power: Power,
+        storage: Storage,
+        watchdog: Watchdog,
+    }
 
     #[init]
     fn init(mut cx: init::Context) -> (Shared, Local, init::Monotonics) {
-        rtt_init_print!();
+        // plain text on the first channel, defmt frames on
+        // the second, and the console coming in
+        let channels = rtt_init! {
+            up: {
+                0: {
+                    size: 1024
+                    name: "Terminal"
+                }
+                1: {
+                    size: 1024
+                    name: "defmt"
+                }
+            }
+            down: {
+                0: {
+                    size: 64
+                    name: "Terminal"
+                }
+            }
+        };
+
+        set_print_channel(channels.up.0);
+        log::init(channels.up.1);
+
+        let reset_cause = ResetCause::read(&cx.device.RCC);
+        let watchdog = Watchdog::new(cx.device.IWDG, &cx.device.DBGMCU);
+
+        info!("reset by {}", reset_cause.name());
 
         let mut syscfg = cx.device.SYSCFG.constrain();
         let rcc = cx.device.RCC.constrain();
         let clocks = rcc.cfgr.freeze();
         let mono = MonoTimer::new(cx.device.TIM2, &clocks);
 
-        let gpioa = cx.device.GPIOA.split();
-        let gpiob = cx.device.GPIOB.split();
+        let peripherals = board::peripherals!(cx.device);
+        let pins = Pins::new(
+            cx.device.GPIOA.split(),
+            cx.device.GPIOB.split(),
+            cx.device.GPIOC.split(),
+        );
 
-        let audio_output_dsp = gpiob.pb12.into_push_pull_output();
-        let audio_output_ctrl = gpiob.pb13.into_push_pull_output();
-        let audio_mute_ctrl = gpiob.pb14.into_push_pull_output();
+        let audio_dsp = AudioDsp::new(I2c::new(
+            peripherals.dsp_bus,
+            pins.dsp_bus,
+            100.khz(),
+            &clocks,
+        ));
+        let audio_volume = AudioVolume::new(
+            Spi::new(
+                peripherals.volume_bus,
+                pins.volume_bus,
+                Mode {
+                    polarity: Polarity::IdleLow,
+                    phase: Phase::CaptureOnFirstTransition,
+                },
+                1.mhz(),
+                &clocks,
+            ),
+            pins.audio_volume_cs,
+        );
 
         let brightness_output =
-            Timer::new(cx.device.TIM10, &clocks).pwm(gpiob.pb8.into_alternate(), 24.khz());
+            Timer::new(peripherals.brightness_timer, &clocks).pwm(pins.brightness, 24.khz());
 
-        let mut meter_clock = gpioa.pa8.into_pull_up_input();
+        let (remote_tx, remote_rx) = Serial::new(
+            peripherals.remote_serial,
+            pins.remote,
+            115_200.bps(),
+            &clocks,
+        )
+        .unwrap()
+        .split();
+
+        let ambient_sensor = Phototransistor::new(
+            Adc::adc1(peripherals.ambient_adc, true, AdcConfig::default()),
+            pins.ambient,
+        );
+
+        let mut meter_clock = pins.meter_clock;
 
         meter_clock.make_interrupt_source(&mut syscfg);
         meter_clock.enable_interrupt(&mut cx.device.EXTI);
         meter_clock.trigger_on_edge(&mut cx.device.EXTI, Edge::RisingFalling);
 
-        let meter_input = MeterInput::new(
-            meter_clock,
-            gpioa.pa10.into_pull_up_input(),
-            gpioa.pa11.into_pull_up_input(),
-        );
+        let meter_input = MeterInput::new(meter_clock, pins.meter_data_inputs);
 
         let meter_register = MeterRegister {
             buffer: ShiftBuffer::new(),
-            data: gpiob.pb5.into_push_pull_output(),
-            latch: gpiob.pb6.into_push_pull_output(),
-            clock: gpiob.pb7.into_push_pull_output(),
+            data: pins.meter_data,
+            latch: pins.meter_latch,
+            clock: pins.meter_register_clock,
         };
 
-        let key_trigger = gpioa.pa12.into_pull_down_input();
+        let encoder = Encoder::new(pins.encoder_a, pins.encoder_b, pins.encoder_switch);
+
+        let jack = HeadphoneJack::new(pins.headphone_detect);
+        let protection = Protection::new(pins.fault);
+
+        // the trigger only raises an event, to wake the core
+        // from a stop in standby
+        let mut key_trigger = pins.key_trigger;
+
+        key_trigger.make_interrupt_source(&mut syscfg);
+        key_trigger.trigger_on_edge(&mut cx.device.EXTI, Edge::Rising);
+
+        let power = Power::new(
+            cx.device.PWR,
+            cx.device.RTC,
+            cx.device.EXTI,
+            cx.core.SCB,
+            &cx.device.DBGMCU,
+        );
         let key_register = KeyRegister {
             buffer: ShiftBuffer::new(),
-            data: gpiob.pb4.into_push_pull_output(),
-            latch: gpiob.pb3.into_push_pull_output(),
-            clock: gpioa.pa15.into_push_pull_output(),
+            data: pins.key_data,
+            latch: pins.key_latch,
+            clock: pins.key_clock,
         };
 
+        let mut storage = Storage::new(cx.device.FLASH);
+        let mut settings = storage.load().unwrap_or_default();
+
+        if reset_cause == ResetCause::Watchdog {
+            settings.watchdog_resets = settings.watchdog_resets.saturating_add(1);
+        }
+
+        let mut remote = Remote::new(remote_tx, remote_rx);
+
+        remote.reset(reset_cause);
+
+        if let Some(crash) = crash::last() {
+            warn!("crashed before the last reset");
+
+            remote.crash(Some(crash));
+        }
+
         keypad::spawn().ok();
         clock::spawn().ok();
+        ambient::spawn().ok();
+        tick::spawn().ok();
+        watchdog::spawn().ok();
+        diagnostics::spawn_after(SAMPLE_MS.millis()).ok();
+        console::spawn().ok();
 
         Booted.send();
 
         (
             Shared {
                 brightness: Brightness::new(brightness_output),
-                control: Control::new(audio_output_dsp, audio_output_ctrl, audio_mute_ctrl),
+                control: Control::new(
+                    AudioPins {
+                        output_ctrl: pins.audio_output_ctrl,
+                        mute_ctrl: pins.audio_mute_ctrl,
+                        input_select: pins.audio_input_select,
+                    },
+                    audio_volume,
+                ),
+                encoder,
+                jack,
                 keypad: Keypad::new(key_trigger, key_register),
-                meter: Meter::new(meter_input, meter_register),
-                state: State::Booting,
+                meter: Meter::new(meter_input, meter_register, pins.segment_sense),
+                protection,
+                remote,
+                state: State::Booting { settings },
+            },
+            Local {
+                ambient: Ambient::new(ambient_sensor),
+                audio_dsp,
+                console: Console::new(channels.down.0, reset_cause),
+                diagnostics: Diagnostics::new(time::now()),
+                power,
+                storage,
+                watchdog,
             },
-            Local {},
             init::Monotonics(mono),
         )
     }
 
     #[idle(
+        local = [
+            audio_dsp,
+            power,
+            storage,
+        ],
         shared = [
             control,
             brightness,
+            keypad,
             meter,
+            remote,
             state,
         ]
     )]
@@ -118,19 +326,56 @@ mod inner {
         let idle::SharedResources {
             mut control,
             mut brightness,
+            mut keypad,
             mut meter,
+            mut remote,
             mut state,
         } = cx.shared;
+        let idle::LocalResources {
+            audio_dsp,
+            power,
+            storage,
+        } = cx.local;
 
         loop {
-            if let Some(msg) = Q.dequeue() {
+            progress(Task::Idle);
+
+            // the dsp's bus blocks for milliseconds, so it's
+            // only written here, outside the lock, where the
+            // clock can still preempt it
+            while let Some(write) = control.lock(|control| control.dsp_write()) {
+                let status = write.apply(audio_dsp);
+
+                control.lock(|control| control.dsp_written(write, status));
+            }
+
+            if let Some(msg) = queue::next() {
                 state.lock(|state| {
-                    *state = state.recv(msg);
+                    let name = state.name();
+
+                    *state = state.recv(msg, time::now());
+
+                    if state.name() != name {
+                        log::event(Event::State(state.name()));
+                    }
 
                     brightness.lock(|brightness| brightness.write(state));
-                    control.lock(|control| control.write(state));
+                    control.lock(|control| control.write(state, time::now()));
+                    keypad.lock(|keypad| keypad.write(state));
                     meter.lock(|meter| meter.write(state));
+                    remote.lock(|remote| remote.write(state));
+
+                    storage.write(state);
                 });
+            } else if keypad.lock(|keypad| keypad.is_armed()) {
+                // a message sent since the queue was checked
+                // leaves an event behind, so this won't stop
+                power.stop();
+
+                keypad.lock(|keypad| keypad.disarm());
+                feed_stopped();
+            } else {
+                slept(power.sleep());
             }
         }
     }
@@ -138,16 +383,40 @@ mod inner {
     #[task(
         priority = 1,
         shared = [
+            brightness,
+            control,
+            encoder,
             keypad,
             meter,
+            protection,
         ],
     )]
     fn clock(cx: clock::Context) {
         let clock::SharedResources {
+            mut brightness,
+            mut control,
+            mut encoder,
             mut keypad,
             mut meter,
+            mut protection,
         } = cx.shared;
 
+        protection.lock(|protection| {
+            protection.read();
+        });
+
+        brightness.lock(|brightness| {
+            brightness.clock();
+        });
+
+        control.lock(|control| {
+            control.clock(time::now());
+        });
+
+        encoder.lock(|encoder| {
+            encoder.read();
+        });
+
         meter.lock(|meter| {
             meter.clock();
         });
@@ -156,23 +425,110 @@ mod inner {
             keypad.clock();
         });
 
-        clock::spawn_after(50.micros()).ok();
+        progress(Task::Clock);
+
+        clock::spawn_after((1_000_000 / CLOCK_HZ).micros()).ok();
     }
 
     #[task(
         priority = 2,
         shared = [
+            jack,
             keypad,
         ],
     )]
     fn keypad(cx: keypad::Context) {
-        let keypad::SharedResources { mut keypad } = cx.shared;
+        let keypad::SharedResources {
+            mut jack,
+            mut keypad,
+        } = cx.shared;
 
+        jack.lock(|jack| jack.read());
         keypad.lock(|keypad| keypad.read());
 
+        progress(Task::Keypad);
+
         keypad::spawn_after(20.millis()).ok();
     }
 
+    #[task(
+        priority = 1,
+        local = [
+            ambient,
+        ],
+    )]
+    fn ambient(cx: ambient::Context) {
+        cx.local.ambient.read();
+
+        ambient::spawn_after(100.millis()).ok();
+    }
+
+    #[task(priority = 1)]
+    fn tick(_: tick::Context) {
+        Tick.send();
+
+        tick::spawn_after(TICK_MS.millis()).ok();
+    }
+
+    #[task(
+        priority = 1,
+        local = [
+            watchdog,
+        ],
+    )]
+    fn watchdog(cx: watchdog::Context) {
+        cx.local.watchdog.check();
+
+        watchdog::spawn_after(CHECK_MS.millis()).ok();
+    }
+
+    #[task(
+        priority = 1,
+        local = [
+            diagnostics,
+        ],
+        shared = [
+            remote,
+        ],
+    )]
+    fn diagnostics(mut cx: diagnostics::Context) {
+        if cx.local.diagnostics.sample(time::now()) {
+            let snapshot = snapshot();
+
+            info!("diagnostics {}", Display2Format(&snapshot));
+
+            cx.shared.remote.lock(|remote| remote.diagnostics(snapshot));
+        }
+
+        diagnostics::spawn_after(SAMPLE_MS.millis()).ok();
+    }
+
+    #[task(
+        priority = 1,
+        local = [
+            console,
+        ],
+        shared = [
+            control,
+            state,
+        ],
+    )]
+    fn console(cx: console::Context) {
+        let console::SharedResources {
+            mut control,
+            mut state,
+        } = cx.shared;
+        let console = cx.local.console;
+
+        while let Some(command) = console.read() {
+            let reset_cause = console.reset_cause();
+
+            run(command, console, reset_cause, &mut state, &mut control);
+        }
+
+        console::spawn_after(POLL_MS.millis()).ok();
+    }
+
     #[task(
         binds = EXTI9_5,
         priority = 2,
@@ -185,4 +541,31 @@ mod inner {
 
         meter.lock(|meter| meter.read());
     }
+
+    #[task(
+        binds = USART2,
+        priority = 1,
+        shared = [
+            control,
+            remote,
+            state,
+        ]
+    )]
+    fn remote(cx: remote::Context) {
+        let remote::SharedResources {
+            mut control,
+            mut remote,
+            mut state,
+        } = cx.shared;
+
+        remote.lock(|remote| {
+            if let Some(command) = remote.read() {
+                let reset_cause = remote.reset_cause();
+
+                run(command, remote, reset_cause, &mut state, &mut control);
+            }
+
+            remote.flush();
+        });
+    }
 }
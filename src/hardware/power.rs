@@ -0,0 +1,121 @@
+use crate::hardware::{time, TimeDuration};
+use cortex_m::asm::wfe;
+use cortex_m::interrupt;
+use cortex_m::peripheral::SCB;
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::elapsed;
+use stm32f4xx_hal::pac::{DBGMCU, EXTI, PWR, RCC, RTC};
+
+/// how long the core stays stopped before waking to
+/// feed the watchdog, well inside its timeout even with
+/// the lsi running slow
+const STOP_WAKEUP_MS: u32 = 2000;
+/// the wakeup timer counts the rtc clock divided by 16,
+/// which is the lsi at roughly 32khz
+const WAKEUP_TICKS_PER_MS: u32 = 2;
+
+/// puts the core to sleep between messages, or stops
+/// it altogether in standby.
+///
+/// the clocks are left on the hsi, which is what the
+/// core wakes from a stop on, so there's nothing to
+/// restore afterwards.
+pub struct Power {
+    rtc: RTC,
+    exti: EXTI,
+    scb: SCB,
+}
+
+impl Power {
+    pub fn new(pwr: PWR, rtc: RTC, exti: EXTI, mut scb: SCB, dbgmcu: &DBGMCU) -> Self {
+        // the rcc has been handed to the hal by now, which
+        // leaves the power and backup domain clocks alone
+        let rcc = unsafe { &*RCC::ptr() };
+
+        // keep the debugger and rtt working while asleep in
+        // debug builds. it keeps the clocks running through a
+        // stop, so release builds clear it, as only a power
+        // on reset would
+        let debug = cfg!(debug_assertions);
+
+        dbgmcu
+            .cr
+            .modify(|_, w| w.dbg_sleep().bit(debug).dbg_stop().bit(debug));
+
+        // an interrupt held off around the wfe still has to
+        // wake it
+        scb.set_sevonpend();
+
+        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        pwr.cr
+            .modify(|_, w| w.dbp().set_bit().lpds().set_bit().fpds().set_bit());
+
+        rcc.csr.modify(|_, w| w.lsion().set_bit());
+        while rcc.csr.read().lsirdy().bit_is_clear() {}
+
+        // the backup domain survives a reset, and it has to
+        // be reset itself to change the rtc's clock
+        let bdcr = rcc.bdcr.read();
+
+        if !bdcr.rtcsel().is_lsi() || bdcr.rtcen().bit_is_clear() {
+            rcc.bdcr.modify(|_, w| w.bdrst().set_bit());
+            rcc.bdcr.modify(|_, w| w.bdrst().clear_bit());
+            rcc.bdcr.modify(|_, w| w.rtcsel().lsi().rtcen().set_bit());
+        }
+
+        rtc.wpr.write(|w| w.key().bits(0xca));
+        rtc.wpr.write(|w| w.key().bits(0x53));
+
+        rtc.cr.modify(|_, w| w.wute().clear_bit());
+        while rtc.isr.read().wutwf().bit_is_clear() {}
+
+        rtc.wutr.write(|w| {
+            w.wut()
+                .bits((STOP_WAKEUP_MS * WAKEUP_TICKS_PER_MS - 1) as u16)
+        });
+        rtc.cr
+            .modify(|_, w| unsafe { w.wucksel().bits(0b000).wutie().set_bit().wute().set_bit() });
+
+        rtc.wpr.write(|w| w.key().bits(0xff));
+
+        // the wakeup timer and the key trigger only raise
+        // events, which wake the core without an interrupt
+        exti.rtsr.modify(|_, w| w.tr22().set_bit());
+        exti.emr.modify(|_, w| w.mr12().set_bit().mr22().set_bit());
+
+        Self { rtc, exti, scb }
+    }
+
+    /// sleep until an interrupt or a message is sent,
+    /// returns how long it slept.
+    ///
+    /// the monotonic timer keeps counting while the core
+    /// sleeps, whatever the debug settings. interrupts
+    /// are held off until it's read, so the time spent
+    /// handling the one that woke it up counts as awake.
+    pub fn sleep(&mut self) -> TimeDuration {
+        interrupt::free(|_| {
+            let start = time::now();
+
+            wfe();
+
+            elapsed(start, time::now())
+        })
+    }
+
+    /// stop the core until a key is pressed or it's time
+    /// to feed the watchdog
+    pub fn stop(&mut self) {
+        // the meter clock would wake it straight back up
+        self.exti.imr.modify(|_, w| w.mr8().clear_bit());
+        self.rtc.isr.modify(|_, w| w.wutf().clear_bit());
+        self.exti.pr.write(|w| w.pr22().set_bit());
+
+        self.scb.set_sleepdeep();
+        wfe();
+        self.scb.clear_sleepdeep();
+
+        self.exti.imr.modify(|_, w| w.mr8().set_bit());
+    }
+}
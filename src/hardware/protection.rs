@@ -0,0 +1,39 @@
+use crate::hardware::board::{FaultInput, FAULT_ACTIVE_HIGH};
+use crate::hardware::debounce::*;
+use fugit::ExtU32;
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::Message::*;
+
+/// how long the fault input has to hold a level before
+/// it's believed, kept short as this is protecting the
+/// speakers
+const SETTLE_MS: u32 = 2;
+
+pub struct Protection {
+    fault: FaultInput,
+    faulted: bool,
+    debouncer: Debouncer<2, bool>,
+}
+
+impl Protection {
+    pub fn new(fault: FaultInput) -> Self {
+        Self {
+            fault,
+            faulted: false,
+            debouncer: Debouncer::new(),
+        }
+    }
+
+    pub fn read(&mut self) {
+        let faulted = self.fault.is_high() == FAULT_ACTIVE_HIGH;
+
+        if faulted == self.faulted {
+            self.debouncer.update(!faulted, SETTLE_MS.millis());
+        } else if self.debouncer.is_ok(faulted) {
+            self.faulted = faulted;
+
+            FaultUpdate(faulted).send();
+        }
+    }
+}
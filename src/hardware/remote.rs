@@ -0,0 +1,145 @@
+use crate::hardware::board::RemoteSerial;
+use crate::hardware::report::Segments;
+use crate::hardware::watchdog::ResetCause;
+use crate::log;
+use core::fmt::{self, Write};
+use heapless::{Deque, String};
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::command::{self, Command};
+use runtime::{State, State::*};
+use stm32f4xx_hal::{
+    hal::serial::{Read, Write as _},
+    serial::{Rx, Tx},
+};
+
+pub type RemoteTx = Tx<RemoteSerial>;
+pub type RemoteRx = Rx<RemoteSerial>;
+
+/// the serial control interface.
+///
+/// commands are read a line at a time and replies are
+/// queued, then sent a byte per tx empty interrupt so
+/// writing never blocks the caller.
+pub struct Remote {
+    tx: RemoteTx,
+    rx: RemoteRx,
+    line: String<64>,
+    output: Deque<u8, 512>,
+    faulted: bool,
+    self_test_reported: bool,
+    reset_cause: ResetCause,
+}
+
+impl Remote {
+    pub fn new(tx: RemoteTx, mut rx: RemoteRx) -> Self {
+        rx.listen();
+
+        Self {
+            tx,
+            rx,
+            line: String::new(),
+            output: Deque::new(),
+            faulted: false,
+            self_test_reported: false,
+            reset_cause: ResetCause::Unknown,
+        }
+    }
+
+    /// report why the unit last reset
+    pub fn reset(&mut self, reset_cause: ResetCause) {
+        self.reset_cause = reset_cause;
+
+        writeln!(self, "reset by {}", reset_cause.name()).ok();
+    }
+
+    pub fn reset_cause(&self) -> ResetCause {
+        self.reset_cause
+    }
+
+    /// read a received byte, returning the command once
+    /// a full line has come in
+    pub fn read(&mut self) -> Option<Command> {
+        let byte = self.rx.read().ok()?;
+
+        if byte != b'\r' && byte != b'\n' {
+            if self.line.push(byte as char).is_err() {
+                self.line.clear();
+            }
+
+            return None;
+        }
+
+        if self.line.is_empty() {
+            return None;
+        }
+
+        let command = command::parse(&self.line);
+
+        if command.is_none() {
+            let line = self.line.clone();
+
+            writeln!(self, "unknown command: {}", line).ok();
+        }
+
+        self.line.clear();
+
+        command
+    }
+
+    /// report changes in state that aren't asked for
+    pub fn write(&mut self, state: &State) {
+        while let Some(event) = log::mirrored() {
+            writeln!(self, "log {}", event).ok();
+        }
+
+        let faulted = matches!(state, Fault { .. });
+
+        if faulted != self.faulted {
+            self.faulted = faulted;
+
+            writeln!(self, "{}", if faulted { "fault" } else { "fault cleared" }).ok();
+        }
+
+        match state {
+            SelfTest {
+                dead_segments: Some(dead),
+                ..
+            } if !self.self_test_reported => {
+                self.self_test_reported = true;
+
+                if dead.iter().any(|dead| *dead != 0) {
+                    writeln!(self, "self-test failed{}", Segments(*dead)).ok();
+                }
+            }
+            SelfTest { .. } => {}
+            _ => self.self_test_reported = false,
+        }
+    }
+
+    /// move the next queued byte into the transmitter
+    pub fn flush(&mut self) {
+        if !self.tx.is_tx_empty() {
+            return;
+        }
+
+        match self.output.pop_front() {
+            Some(byte) => {
+                self.tx.write(byte).ok();
+            }
+            None => self.tx.unlisten(),
+        }
+    }
+}
+
+impl Write for Remote {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.output.push_back(byte).map_err(|_| fmt::Error)?;
+        }
+
+        self.tx.listen();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use crate::hardware::board::{METER_CHANNEL_NAMES, METER_SEGMENTS};
+use crate::hardware::crash::{Crash, CrashKind};
+use crate::hardware::diagnostics::Snapshot;
+use crate::hardware::watchdog::ResetCause;
+use core::fmt::{self, Write};
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::attenuator::volume_to_db;
+use runtime::control::AudioOutput;
+use runtime::dsp::DspStatus;
+use runtime::meter::{is_lit, SegmentMask};
+use runtime::queue::QueueStats;
+use runtime::{State, State::*};
+
+/// replies to the control protocol's queries, the same
+/// on the serial port and the rtt console
+pub trait Report: Write {
+    fn status(&mut self, state: &State) {
+        match state {
+            Booting { .. } => writeln!(self, "booting").ok(),
+            SelfTest { .. } => writeln!(self, "self-test").ok(),
+            Running { settings, .. } => writeln!(
+                self,
+                "running output={} input={} mute={} volume={} preset={} brightness={} auto-brightness={} dimmed={} peaks={} levels={}",
+                match settings.audio_output {
+                    AudioOutput::Headphones => "headphones",
+                    AudioOutput::Speakers => "speakers",
+                },
+                settings.audio_input + 1,
+                on_off(settings.audio_mute),
+                Db(volume_to_db(settings.audio_volume)),
+                settings.dsp_presets[settings.audio_output as usize] + 1,
+                settings.shown_brightness(),
+                on_off(settings.auto_brightness),
+                on_off(settings.auto_dim.is_dimmed()),
+                on_off(settings.peaks),
+                on_off(settings.levels),
+            )
+            .ok(),
+            Fault { cleared, .. } => writeln!(
+                self,
+                "fault {}",
+                if cleared.is_some() {
+                    "recovering"
+                } else {
+                    "active"
+                }
+            )
+            .ok(),
+            Menu { position, .. } => writeln!(
+                self,
+                "menu {}{}",
+                position.item().name(),
+                if position.editing { " editing" } else { "" }
+            )
+            .ok(),
+            Standby { .. } => writeln!(self, "standby").ok(),
+        };
+    }
+
+    /// the meter channels as the state sees them, with
+    /// the segments from the top down
+    fn meter(&mut self, state: &State) {
+        match state {
+            Running { channels, .. } => {
+                for (name, channel) in METER_CHANNEL_NAMES.iter().zip(channels) {
+                    writeln!(
+                        self,
+                        "meter {} level={:0width$b} peak={:0width$b} partial={} calculated={}",
+                        name,
+                        channel.level,
+                        channel.peak,
+                        channel.partial,
+                        channel.calculated,
+                        width = METER_SEGMENTS
+                    )
+                    .ok();
+                }
+            }
+            _ => {
+                writeln!(self, "meter not running").ok();
+            }
+        }
+    }
+
+    fn crash(&mut self, crash: Option<Crash>) {
+        match crash {
+            Some(crash) if crash.kind() == CrashKind::Panic => {
+                writeln!(self, "crash panic {}", crash.message()).ok()
+            }
+            Some(crash) => writeln!(
+                self,
+                "crash hard-fault pc={:#010x} lr={:#010x} cfsr={:#010x}",
+                crash.pc, crash.lr, crash.cfsr
+            )
+            .ok(),
+            None => writeln!(self, "no crash logged").ok(),
+        };
+    }
+
+    /// report the last reset and how often the watchdog
+    /// has had to step in
+    fn reset_status(&mut self, reset_cause: ResetCause, state: &State) {
+        let watchdog_resets = state.settings().watchdog_resets;
+
+        writeln!(
+            self,
+            "reset-cause={} watchdog-resets={}",
+            reset_cause.name(),
+            watchdog_resets
+        )
+        .ok();
+    }
+
+    fn diagnostics(&mut self, snapshot: Snapshot) {
+        writeln!(self, "diagnostics {}", snapshot).ok();
+    }
+
+    fn queue_status(&mut self, stats: QueueStats) {
+        writeln!(
+            self,
+            "queue dropped={} overflowed-input={} coalesced={} \
+             input-high-water={} other-high-water={}",
+            stats.dropped,
+            stats.overflowed_input,
+            stats.coalesced,
+            stats.input_high_water,
+            stats.other_high_water
+        )
+        .ok();
+    }
+
+    fn dsp_status(&mut self, status: Option<DspStatus>) {
+        match status {
+            Some(DspStatus { core_control }) => {
+                writeln!(self, "dsp core-control={:#06x}", core_control).ok()
+            }
+            None => writeln!(self, "dsp not responding").ok(),
+        };
+    }
+}
+
+impl<W: Write> Report for W {}
+
+/// a gain in db, or fully attenuated
+struct Db(Option<f32>);
+
+impl fmt::Display for Db {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(db) => write!(f, "{}db", db),
+            None => write!(f, "off"),
+        }
+    }
+}
+
+/// the segments in a self test mask, by channel and
+/// counting from the bottom
+pub struct Segments(pub SegmentMask);
+
+impl fmt::Display for Segments {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, segments) in METER_CHANNEL_NAMES.iter().zip(self.0) {
+            if segments == 0 {
+                continue;
+            }
+
+            write!(f, " {}", name)?;
+
+            for index in 0..METER_SEGMENTS {
+                if is_lit(segments, index) {
+                    write!(f, " {}", index + 1)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
@@ -0,0 +1,202 @@
+use crate::hardware::board::{SegmentSense, METER_CHANNELS, METER_SEGMENTS};
+use crate::hardware::{TimeInstant, CLOCK_HZ};
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::meter::{bar, dot, SegmentMask};
+use runtime::modulation::frame_ticks;
+use runtime::settings::BootSequence;
+use runtime::{elapsed, Message::*};
+
+/// how long each step of the sweep is shown
+const SWEEP_STEP_MS: u32 = 25;
+/// how long each segment is lit on its own
+const TEST_STEP_MS: u32 = 20;
+/// how long everything is lit after a failure, so the
+/// dead segments show up as gaps
+const FAILURE_MS: u32 = 3000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Idle,
+    Sweep {
+        start: TimeInstant,
+    },
+    Test {
+        segment: u32,
+        start: TimeInstant,
+        seen: bool,
+    },
+    Failed {
+        start: TimeInstant,
+    },
+    Done,
+}
+
+/// sweeps the meter up and down, then lights each
+/// segment on its own and checks it draws current.
+///
+/// segments are numbered from the bottom of the first
+/// channel up, then the bottom of the next channel up.
+pub struct SelfTestSequence<const SEGMENTS: usize, const CHANNELS: usize> {
+    sense: SegmentSense,
+    sequence: BootSequence,
+    step: Step,
+    dead: SegmentMask<CHANNELS>,
+}
+
+impl<const SEGMENTS: usize, const CHANNELS: usize> SelfTestSequence<SEGMENTS, CHANNELS> {
+    /// how long a segment is given to light before the
+    /// sense input is believed, the frame already being
+    /// shifted out and then the one carrying it
+    const SENSE_SETTLE_US: u32 =
+        2 * frame_ticks(SEGMENTS * CHANNELS, CLOCK_HZ) * (1_000_000 / CLOCK_HZ);
+
+    /// the segment has to have lit well before its step
+    /// is over
+    const SETTLES: () = assert!(Self::SENSE_SETTLE_US * 2 <= TEST_STEP_MS * 1000);
+
+    pub fn new(sense: SegmentSense) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::SETTLES;
+
+        Self {
+            sense,
+            sequence: BootSequence::Full,
+            step: Step::Idle,
+            dead: [0; CHANNELS],
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.step == Step::Idle
+    }
+
+    pub fn start(&mut self, sequence: BootSequence, now: TimeInstant) {
+        self.sequence = sequence;
+        self.dead = [0; CHANNELS];
+        self.step = Step::Sweep { start: now };
+    }
+
+    /// stop any sequence so the next one can start
+    pub fn reset(&mut self) {
+        self.step = Step::Idle;
+    }
+}
+
+// the result is sent in the runtime's own size
+impl SelfTestSequence<METER_SEGMENTS, METER_CHANNELS> {
+    /// advance the sequence, returning which segments to
+    /// light while it's running
+    pub fn clock(&mut self, now: TimeInstant) -> Option<SegmentMask> {
+        let segments = METER_SEGMENTS as u32;
+
+        match self.step {
+            Step::Idle | Step::Done => None,
+
+            // fill every channel up, then empty them again
+            Step::Sweep { start } => {
+                let step = elapsed(start, now).to_millis() / SWEEP_STEP_MS;
+
+                if step >= segments * 2 {
+                    self.next_after_sweep(now);
+
+                    return Some([0; METER_CHANNELS]);
+                }
+
+                let height = if step < segments {
+                    step + 1
+                } else {
+                    segments * 2 - step - 1
+                };
+
+                Some([bar(height as usize); METER_CHANNELS])
+            }
+
+            Step::Test {
+                segment,
+                start,
+                seen,
+            } => {
+                let elapsed = elapsed(start, now);
+                let seen =
+                    seen || elapsed.to_micros() >= Self::SENSE_SETTLE_US && self.sense.is_high();
+
+                self.step = Step::Test {
+                    segment,
+                    start,
+                    seen,
+                };
+
+                let (channel, index) = (
+                    segment as usize / METER_SEGMENTS,
+                    segment as usize % METER_SEGMENTS,
+                );
+
+                if elapsed.to_millis() >= TEST_STEP_MS {
+                    if !seen {
+                        self.dead[channel] |= dot(index + 1);
+                    }
+
+                    if segment as usize + 1 < METER_SEGMENTS * METER_CHANNELS {
+                        self.step = Step::Test {
+                            segment: segment + 1,
+                            start: now,
+                            seen: false,
+                        };
+                    } else {
+                        self.finish(now);
+
+                        return Some([0; METER_CHANNELS]);
+                    }
+                }
+
+                let mut lit = [0; METER_CHANNELS];
+
+                lit[channel] = dot(index + 1);
+
+                Some(lit)
+            }
+
+            Step::Failed { start } => {
+                if elapsed(start, now).to_millis() >= FAILURE_MS {
+                    self.step = Step::Done;
+
+                    SelfTestDone.send();
+
+                    return Some([0; METER_CHANNELS]);
+                }
+
+                Some([bar(METER_SEGMENTS); METER_CHANNELS])
+            }
+        }
+    }
+
+    fn next_after_sweep(&mut self, now: TimeInstant) {
+        match self.sequence {
+            BootSequence::Full => {
+                self.step = Step::Test {
+                    segment: 0,
+                    start: now,
+                    seen: false,
+                }
+            }
+            _ => {
+                self.step = Step::Done;
+
+                SelfTestDone.send();
+            }
+        }
+    }
+
+    fn finish(&mut self, now: TimeInstant) {
+        SelfTestResult(self.dead).send();
+
+        if self.dead.iter().all(|dead| *dead == 0) {
+            self.step = Step::Done;
+
+            SelfTestDone.send();
+        } else {
+            self.step = Step::Failed { start: now };
+        }
+    }
+}
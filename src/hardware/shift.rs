@@ -1,3 +1,4 @@
+use crate::hardware::diagnostics;
 use heapless::Deque;
 #[allow(unused_imports)]
 use rtt_target::*;
@@ -14,14 +15,16 @@ pub enum ShiftState<Id> {
     LatchOff(Id, usize),
 }
 
-pub struct ShiftRegister<const LEN: usize, Id, Data, Latch, Clock> {
+/// a chain of shift registers, the length of the chain
+/// is however many bits are written to it
+pub struct ShiftRegister<Id, Data, Latch, Clock> {
     pub buffer: ShiftBuffer<Id>,
     pub data: Data,
     pub latch: Latch,
     pub clock: Clock,
 }
 
-impl<const LEN: usize, Id, Data, Latch, Clock> ShiftRegister<LEN, Id, Data, Latch, Clock>
+impl<Id, Data, Latch, Clock> ShiftRegister<Id, Data, Latch, Clock>
 where
     Id: Copy,
     Data: OutputPin,
@@ -51,7 +54,9 @@ where
         self.buffer.is_empty()
     }
 
-    pub fn write(&mut self, id: Id, mut pattern: usize) {
+    /// queue the bits to be shifted out then latched,
+    /// the first one ends up at the far end of the chain
+    pub fn write(&mut self, id: Id, bits: impl IntoIterator<Item = bool>) {
         use ShiftState::*;
 
         let Self { buffer, .. } = self;
@@ -62,12 +67,8 @@ where
             .push_back((Reset(id), PinState::Low, PinState::Low, PinState::Low))
             .ok();
 
-        while index < LEN {
-            let data_state = if 1 & pattern > 0 && index <= LEN {
-                PinState::High
-            } else {
-                PinState::Low
-            };
+        for bit in bits {
+            let data_state = PinState::from(bit);
 
             buffer
                 .push_back((BitOn(id, index), data_state, PinState::Low, PinState::Low))
@@ -77,7 +78,6 @@ where
                 .push_back((BitOff(id, index), data_state, PinState::Low, PinState::High))
                 .ok();
 
-            pattern >>= 1;
             index += 1;
         }
 
@@ -98,5 +98,7 @@ where
                 PinState::Low,
             ))
             .ok();
+
+        diagnostics::shift_depth(buffer.len());
     }
 }
@@ -0,0 +1,177 @@
+use crate::hardware::{time, TimeInstant};
+use crate::log::{self, error, info, warn, Event};
+#[allow(unused_imports)]
+use rtt_target::*;
+use runtime::settings::{Settings, SETTINGS_LEN};
+use runtime::{elapsed, State, State::*};
+use stm32f4xx_hal::{flash::FlashExt, pac::FLASH};
+
+/// the flash sector given over to settings, the last
+/// one on a 512k part, kept out of the program by
+/// `memory.x`
+const SECTOR: u8 = 7;
+const SECTOR_OFFSET: usize = 0x0006_0000;
+const SECTOR_LEN: usize = 0x0002_0000;
+
+/// marks the start of a record, and bumped whenever
+/// the settings are packed differently
+const MAGIC: [u8; 3] = [b'v', b'u', 4];
+/// records are a whole number of the 16 byte blocks the
+/// flash is programmed in
+const RECORD_LEN: usize = 64;
+const RECORDS: usize = SECTOR_LEN / RECORD_LEN;
+
+/// how long settings have to stay put before they're
+/// saved, so turning the encoder doesn't write each step
+const SAVE_DELAY_MS: u32 = 5000;
+
+/// keeps the settings in flash across power cycles.
+///
+/// each save is appended to the sector as a new record,
+/// and the last good record is the one loaded, so the
+/// sector is only erased once it has filled up. the
+/// core stalls while the flash is busy, which is short
+/// for a record but takes a second or two for an erase.
+pub struct Storage {
+    flash: FLASH,
+    next: usize,
+    saved: [u8; SETTINGS_LEN],
+    pending: [u8; SETTINGS_LEN],
+    changed: Option<TimeInstant>,
+}
+
+impl Storage {
+    pub fn new(flash: FLASH) -> Self {
+        Self {
+            flash,
+            next: 0,
+            saved: [0; SETTINGS_LEN],
+            pending: [0; SETTINGS_LEN],
+            changed: None,
+        }
+    }
+
+    /// find the last good record, and where the next
+    /// one is to go
+    pub fn load(&mut self) -> Option<Settings> {
+        if self.flash.len() < SECTOR_OFFSET + SECTOR_LEN {
+            warn!("no flash sector for settings, using defaults");
+
+            return None;
+        }
+
+        let sector = &self.flash.read()[SECTOR_OFFSET..SECTOR_OFFSET + SECTOR_LEN];
+        let mut loaded = None;
+
+        self.next = RECORDS;
+
+        for (index, record) in sector.chunks_exact(RECORD_LEN).enumerate() {
+            if record.iter().all(|byte| *byte == 0xff) {
+                self.next = index;
+
+                break;
+            }
+
+            if let Some(settings) = payload(record).and_then(Settings::load) {
+                loaded = Some(settings);
+            }
+        }
+
+        match loaded {
+            Some(settings) => {
+                info!("loaded settings from flash");
+
+                self.saved = settings.save();
+                self.pending = self.saved;
+            }
+            None => info!("no saved settings, using defaults"),
+        }
+
+        loaded
+    }
+
+    /// save the settings once they've settled, only while
+    /// running so the safe settings forced by a fault
+    /// aren't kept
+    pub fn write(&mut self, state: &State) {
+        let settings = match state {
+            Running { settings, .. } => Some(*settings),
+            _ => None,
+        };
+
+        if let Some(settings) = settings {
+            let now = time::now();
+            let bytes = settings.save();
+
+            if bytes != self.pending {
+                self.pending = bytes;
+                self.changed = Some(now);
+            }
+
+            match self.changed {
+                Some(changed) if elapsed(changed, now).to_millis() >= SAVE_DELAY_MS => {
+                    self.changed = None;
+
+                    if self.pending != self.saved {
+                        self.save();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        let mut record = [0xff; RECORD_LEN];
+        let checksum = checksum(&self.pending);
+
+        record[..MAGIC.len()].copy_from_slice(&MAGIC);
+        record[MAGIC.len()] = checksum;
+        record[MAGIC.len() + 1..MAGIC.len() + 1 + SETTINGS_LEN].copy_from_slice(&self.pending);
+
+        let mut flash = self.flash.unlocked();
+
+        if self.next >= RECORDS {
+            info!("settings sector full, erasing");
+
+            if flash.erase(SECTOR).is_err() {
+                error!("failed to erase settings sector");
+
+                return;
+            }
+
+            self.next = 0;
+        }
+
+        let offset = SECTOR_OFFSET + self.next * RECORD_LEN;
+
+        self.next += 1;
+
+        match flash.program(offset, record.iter()) {
+            Ok(()) => {
+                self.saved = self.pending;
+
+                log::event(Event::SettingsSaved);
+            }
+            Err(_) => error!("failed to save settings to flash"),
+        }
+    }
+}
+
+/// the packed settings in a record, if it's whole
+fn payload(record: &[u8]) -> Option<&[u8]> {
+    let (header, rest) = record.split_at(MAGIC.len() + 1);
+    let payload = &rest[..SETTINGS_LEN];
+
+    if header[..MAGIC.len()] != MAGIC || header[MAGIC.len()] != checksum(payload) {
+        return None;
+    }
+
+    Some(payload)
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |sum, byte| sum.rotate_left(1) ^ byte)
+}
@@ -0,0 +1,124 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+#[allow(unused_imports)]
+use rtt_target::*;
+use stm32f4xx_hal::{
+    hal::watchdog::{Watchdog as _, WatchdogEnable},
+    pac::{DBGMCU, IWDG, RCC},
+    prelude::*,
+    watchdog::IndependentWatchdog,
+};
+
+/// how long the tasks can go without progress before
+/// the unit is reset, long enough to cover erasing the
+/// settings sector, which stalls everything
+const TIMEOUT_MS: u32 = 5000;
+/// how often progress is checked
+pub const CHECK_MS: u32 = 100;
+
+/// the tasks that have to keep making progress
+#[derive(Debug, Clone, Copy)]
+pub enum Task {
+    Idle = 0b001,
+    Clock = 0b010,
+    Keypad = 0b100,
+}
+
+const ALL_TASKS: u8 = Task::Idle as u8 | Task::Clock as u8 | Task::Keypad as u8;
+
+static PROGRESS: AtomicU8 = AtomicU8::new(0);
+
+/// note that `task` has made it round its loop
+pub fn progress(task: Task) {
+    PROGRESS.fetch_or(task as u8, Ordering::Relaxed);
+}
+
+/// feed the watchdog after waking from a stop, when
+/// none of the tasks are expected to have run
+pub fn feed_stopped() {
+    // the watchdog itself belongs to its task, but the
+    // key register can be written from anywhere
+    unsafe { (*IWDG::ptr()).kr.write(|w| w.key().reset()) };
+}
+
+/// why the last reset happened, as the rcc saw it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+    Watchdog,
+    Software,
+    LowPower,
+    PowerOn,
+    BrownOut,
+    Pin,
+    Unknown,
+}
+
+impl ResetCause {
+    /// read the reset flags, then clear them so the next
+    /// reset starts afresh
+    pub fn read(rcc: &RCC) -> Self {
+        use ResetCause::*;
+
+        let csr = rcc.csr.read();
+
+        // a power on reset also sets the brown out and pin
+        // flags, so the order here matters
+        let cause = if csr.wdgrstf().bit_is_set() || csr.wwdgrstf().bit_is_set() {
+            Watchdog
+        } else if csr.sftrstf().bit_is_set() {
+            Software
+        } else if csr.lpwrrstf().bit_is_set() {
+            LowPower
+        } else if csr.porrstf().bit_is_set() {
+            PowerOn
+        } else if csr.borrstf().bit_is_set() {
+            BrownOut
+        } else if csr.padrstf().bit_is_set() {
+            Pin
+        } else {
+            Unknown
+        };
+
+        rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+        cause
+    }
+
+    pub fn name(self) -> &'static str {
+        use ResetCause::*;
+
+        match self {
+            Watchdog => "watchdog",
+            Software => "software",
+            LowPower => "low-power",
+            PowerOn => "power-on",
+            BrownOut => "brown-out",
+            Pin => "pin",
+            Unknown => "unknown",
+        }
+    }
+}
+
+/// the independent watchdog, only fed once every task
+/// has shown progress since it was last fed
+pub struct Watchdog {
+    iwdg: IndependentWatchdog,
+}
+
+impl Watchdog {
+    pub fn new(iwdg: IWDG, dbgmcu: &DBGMCU) -> Self {
+        let mut iwdg = IndependentWatchdog::new(iwdg);
+
+        iwdg.stop_on_debug(dbgmcu, true);
+        iwdg.start(TIMEOUT_MS.ms());
+
+        Self { iwdg }
+    }
+
+    pub fn check(&mut self) {
+        if PROGRESS.load(Ordering::Relaxed) & ALL_TASKS == ALL_TASKS {
+            PROGRESS.fetch_and(!ALL_TASKS, Ordering::Relaxed);
+
+            self.iwdg.feed();
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::hardware::time;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::{interrupt, register::primask};
+use rtt_target::UpChannel;
+
+// the levels, macros and events are shared with the
+// runtime, only getting the frames out is left here
+pub use runtime::log::*;
+
+defmt::timestamp!(
+    "{=u32:ms}",
+    time::now().ticks() / (runtime::TIMER_HZ / 1000)
+);
+
+/// the rtt channel the frames go out on, rtt-target owns
+/// the control block so defmt-rtt can't be used
+static mut CHANNEL: Option<UpChannel> = None;
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+/// whether interrupts were enabled when the frame began
+static mut RESTORE: bool = false;
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+pub fn init(channel: UpChannel) {
+    interrupt::free(|_| unsafe { *addr_of_mut!(CHANNEL) = Some(channel) });
+}
+
+fn write(bytes: &[u8]) {
+    if let Some(channel) = unsafe { (*addr_of_mut!(CHANNEL)).as_mut() } {
+        channel.write(bytes);
+    }
+}
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let active = primask::read().is_active();
+
+        interrupt::disable();
+
+        if TAKEN.swap(true, Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly");
+        }
+
+        unsafe {
+            *addr_of_mut!(RESTORE) = active;
+            (*addr_of_mut!(ENCODER)).start_frame(write);
+        }
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        (*addr_of_mut!(ENCODER)).end_frame(write);
+
+        TAKEN.store(false, Ordering::Relaxed);
+
+        if *addr_of_mut!(RESTORE) {
+            interrupt::enable();
+        }
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*addr_of_mut!(ENCODER)).write(bytes, write);
+    }
+}
@@ -2,10 +2,10 @@
 #![no_std]
 
 pub mod hardware;
-pub mod runtime;
+pub mod log;
 
 use core::panic::PanicInfo;
-use cortex_m::asm::nop;
+use hardware::crash::{self, CrashKind};
 use rtt_target::*;
 
 #[inline(never)]
@@ -13,7 +13,5 @@ use rtt_target::*;
 fn panic(info: &PanicInfo) -> ! {
     rprintln!("{}", info);
 
-    loop {
-        nop();
-    }
+    crash::record(CrashKind::Panic, 0, 0, Some(info))
 }